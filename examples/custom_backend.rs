@@ -0,0 +1,52 @@
+use std::{future::Future, pin::Pin};
+
+use ask_llm::{Backend, Client, MessageContent, Request, Response, ResponseStream, Result, StreamEvent, Usage, config::AppConfig};
+
+/// Replies with the last message reversed; stands in for an internal gateway.
+struct Reverse;
+impl Backend for Reverse {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(async move {
+			let text = match request.conversation.0.last().map(|m| m.content()) {
+				Some(MessageContent::Text(text)) => text.chars().rev().collect(),
+				_ => String::new(),
+			};
+			Ok(Response {
+				usage: Usage {
+					input: request.estimate_tokens(),
+					output: text.len() as u32 / 4,
+					..Default::default()
+				},
+				text,
+				model: "reverse".to_string(),
+				..Default::default()
+			})
+		})
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(async move {
+			let response = self.conversation(request).await?;
+			let events = [
+				StreamEvent::Text(response.text),
+				StreamEvent::Usage(response.usage),
+				StreamEvent::Stop {
+					reason: Some("end_turn".to_string()),
+					stop_sequence: None,
+				},
+			];
+			Ok(Box::pin(futures::stream::iter(events.map(Ok))) as ResponseStream)
+		})
+	}
+
+	fn name(&self) -> String {
+		"reverse:v1".to_string()
+	}
+}
+
+#[tokio::main]
+async fn main() {
+	let client = Client::with_backend(AppConfig::default(), Reverse).temperature(0.0);
+	let response = client.ask("!dlrow ,olleH").await.unwrap();
+	println!("{}\n{response}", response.text);
+}
@@ -0,0 +1,21 @@
+use std::io::Write as _;
+
+use ask_llm::{Client, Model, StreamEvent};
+use futures::StreamExt as _;
+
+#[tokio::main]
+async fn main() {
+	v_utils::clientside!();
+
+	let mut stream = Client::default().model(Model::Fast).ask_stream("Write a haiku about rust compilers").await.unwrap();
+	while let Some(event) = stream.next().await {
+		match event.unwrap() {
+			StreamEvent::Text(text) => {
+				print!("{text}");
+				std::io::stdout().flush().unwrap();
+			}
+			StreamEvent::Stop { reason, .. } => println!("\n[stop: {reason:?}]"),
+			_ => {}
+		}
+	}
+}
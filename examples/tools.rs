@@ -0,0 +1,22 @@
+use ask_llm::{Client, Model, Tool};
+use serde_json::json;
+
+#[tokio::main]
+async fn main() {
+	v_utils::clientside!();
+
+	let weather = Tool::new(
+		"get_weather",
+		"Current weather for a city",
+		json!({"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}),
+		|input| async move { Ok(format!("Sunny, 21°C in {}", input["city"].as_str().unwrap_or("?"))) },
+	);
+
+	let response = Client::default()
+		.model(Model::Fast)
+		.tools(vec![weather])
+		.ask_with_tools("Should I bring an umbrella in Lisbon today?")
+		.await
+		.unwrap();
+	println!("{}\n\n{response}", response.text);
+}
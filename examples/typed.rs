@@ -0,0 +1,19 @@
+use ask_llm::{Client, Model};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct City {
+	name: String,
+	country: String,
+	population: u64,
+}
+
+#[tokio::main]
+async fn main() {
+	v_utils::clientside!();
+
+	let cities: Vec<City> = Client::default().model(Model::Medium).ask_typed("The 3 most populous cities of Europe").await.unwrap();
+	println!("{cities:#?}");
+}
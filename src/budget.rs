@@ -0,0 +1,213 @@
+use std::{io::Write as _, path::PathBuf, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Backend, Error, Request, Response, Result, Usage};
+
+/// Spending caps, see [Client::budget](crate::Client::budget). Unset caps don't apply.
+///
+/// A request is refused when what was already spent plus its worst-case cost would exceed a cap. Worst case is a rough
+/// estimate of the prompt plus as much output as the backend may produce: [max_tokens](crate::Client::max_tokens), or the model's
+/// ceiling without it.
+///
+/// Only Claude is priced. OpenAI-compatible, Gemini and Ollama replies cost 0, so no cap ever stops them.
+#[derive(Clone, Debug, Default)]
+pub struct Budget {
+	pub per_call_cents: Option<f32> = None,
+	/// Over the lifetime of the [Client](crate::Client)
+	pub per_session_cents: Option<f32> = None,
+	/// Since local midnight. Read from the [Ledger] if the client has one, otherwise only this session counts.
+	pub daily_cents: Option<f32> = None,
+}
+
+/// Append-only record of every reply that wasn't a cache hit, one JSON object per line. See [Client::ledger](crate::Client::ledger).
+#[derive(Clone, Debug)]
+pub struct Ledger {
+	/// Defaults to `ask_llm/ledger.jsonl` under the user's data dir
+	pub path: PathBuf,
+}
+impl Default for Ledger {
+	fn default() -> Self {
+		Self {
+			path: dirs::data_dir().unwrap_or_else(std::env::temp_dir).join("ask_llm").join("ledger.jsonl"),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LedgerEntry {
+	pub timestamp: jiff::Timestamp,
+	/// `provider:model` that answered
+	pub model: String,
+	pub usage: Usage,
+	pub cost_cents: f32,
+}
+
+impl Ledger {
+	pub fn append(&self, entry: &LedgerEntry) -> Result<()> {
+		if let Some(dir) = self.path.parent() {
+			std::fs::create_dir_all(dir)?;
+		}
+		let mut line = serde_json::to_vec(entry)?;
+		line.push(b'\n');
+		// one write per line, so concurrent appenders don't interleave
+		std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&line)?;
+		Ok(())
+	}
+
+	/// All entries, oldest first. A missing file is an empty ledger; unparsable lines are skipped.
+	pub fn entries(&self) -> Result<Vec<LedgerEntry>> {
+		let content = match std::fs::read_to_string(&self.path) {
+			Ok(content) => content,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(e.into()),
+		};
+		Ok(content
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.filter_map(|line| serde_json::from_str(line).inspect_err(|e| tracing::warn!("Skipping ledger line: {e}")).ok())
+			.collect())
+	}
+
+	fn spent_today_cents(&self) -> Result<f32> {
+		let today = jiff::Zoned::now().date();
+		let tz = jiff::tz::TimeZone::system();
+		Ok(self.entries()?.iter().filter(|e| e.timestamp.to_zoned(tz.clone()).date() == today).map(|e| e.cost_cents).sum())
+	}
+}
+
+/// What a [Client](crate::Client) has spent, and the caps it's held to.
+#[derive(Debug, Default)]
+pub(crate) struct Spending {
+	pub budget: Budget,
+	pub ledger: Option<Ledger>,
+	session_cents: Mutex<f32>,
+}
+impl Spending {
+	/// Refuse `request` if sending it to `backend` could take spend over a cap.
+	pub fn check(&self, request: &Request<'_>, backend: &dyn Backend) -> Result<()> {
+		if self.budget.per_call_cents.is_none() && self.budget.per_session_cents.is_none() && self.budget.daily_cents.is_none() {
+			return Ok(());
+		}
+		let worst_case = backend.cost_cents(&Usage {
+			input: request.estimate_tokens(),
+			output: backend.max_output(request).unwrap_or(0),
+			..Default::default()
+		});
+		let session = *self.session_cents.lock().unwrap();
+		let daily = || match &self.ledger {
+			Some(ledger) => ledger.spent_today_cents(),
+			None => Ok(session),
+		};
+
+		if let Some(limit) = self.budget.per_call_cents {
+			exceeds("per-call", limit, 0.0, worst_case)?;
+		}
+		if let Some(limit) = self.budget.per_session_cents {
+			exceeds("session", limit, session, worst_case)?;
+		}
+		if let Some(limit) = self.budget.daily_cents {
+			exceeds("daily", limit, daily()?, worst_case)?;
+		}
+		Ok(())
+	}
+
+	/// Count `response` towards the session and append it to the ledger. Ledger failures are logged, never returned.
+	pub fn record(&self, response: &Response) {
+		if response.cache_hit {
+			return;
+		}
+		*self.session_cents.lock().unwrap() += response.cost_cents;
+		if let Some(ledger) = &self.ledger {
+			let entry = LedgerEntry {
+				timestamp: jiff::Timestamp::now(),
+				model: response.backend.clone(),
+				usage: response.usage,
+				cost_cents: response.cost_cents,
+			};
+			if let Err(e) = ledger.append(&entry) {
+				tracing::warn!("Failed to append to ledger at {}: {e}", ledger.path.display());
+			}
+		}
+	}
+
+	pub fn session_cents(&self) -> f32 {
+		*self.session_cents.lock().unwrap()
+	}
+}
+
+fn exceeds(budget: &'static str, limit_cents: f32, spent_cents: f32, worst_case: f32) -> Result<()> {
+	match spent_cents + worst_case > limit_cents {
+		true => Err(Error::BudgetExceeded {
+			budget,
+			limit_cents,
+			spent_cents,
+			request_cents: worst_case,
+		}),
+		false => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		Conversation, Role, ThinkingLevel,
+		claude::{Claude, ClaudeModel},
+		testing::{request, temp_path},
+	};
+
+	#[test]
+	fn caps_and_ledger() {
+		let ledger = Ledger { path: temp_path("ledger.jsonl") };
+		let spending = Spending {
+			budget: Budget {
+				per_call_cents: Some(5.0),
+				daily_cents: Some(10.0),
+				..
+			},
+			ledger: Some(ledger.clone()),
+			..Default::default()
+		};
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "2+2?");
+		let mut request = Request {
+			max_tokens: Some(100),
+			..request(&conv)
+		};
+		// $15 per million output tokens
+		let sonnet = Claude {
+			api_key: None,
+			model_id: "claude-sonnet-4-5".to_string(),
+			model: ClaudeModel::Sonnet45,
+			url: String::new(),
+		};
+		assert!(spending.check(&request, &sonnet).is_ok());
+		request.max_tokens = Some(6_000);
+		assert!(matches!(spending.check(&request, &sonnet), Err(Error::BudgetExceeded { budget: "per-call", .. })));
+		// up to 64k tokens of output
+		request.max_tokens = None;
+		assert!(matches!(spending.check(&request, &sonnet), Err(Error::BudgetExceeded { budget: "per-call", .. })));
+		request.max_tokens = Some(100);
+
+		let response = Response {
+			text: "4".to_string(),
+			cost_cents: 9.9,
+			usage: Usage::default(),
+			duration: std::time::Duration::ZERO,
+			overhead: std::time::Duration::ZERO,
+			model: "x".to_string(),
+			thinking: ThinkingLevel::None,
+			backend: "ollama:x".to_string(),
+			..
+		};
+		spending.record(&response);
+		assert_eq!(spending.session_cents(), 9.9);
+		assert!(matches!(spending.check(&request, &sonnet), Err(Error::BudgetExceeded { budget: "daily", .. })));
+		let entries = ledger.entries().unwrap();
+		assert_eq!(entries.len(), 1);
+		assert_eq!(entries[0].model, "ollama:x");
+
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+}
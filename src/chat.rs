@@ -0,0 +1,129 @@
+use std::{
+	io::Write as _,
+	path::{Path, PathBuf},
+};
+
+use ask_llm::{Client, Conversation, Message, Model, Role, StreamEvent, ThinkingLevel, Usage};
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+const HELP: &str = "\
+/model <cheap|translate|fast|medium|slow>  switch model tier
+/thinking <none|low|medium|high>           set thinking level
+/attach <path>                             add a file or image to the conversation
+/save [path]                               write the conversation as json
+/load <path>                               continue a saved conversation
+/clear                                     start over
+/cost                                      cost and tokens of this session
+/quit                                      exit (or Ctrl-D)";
+
+/// Interactive multi-turn chat. `path` is loaded if it exists and is where a bare `/save` writes to.
+pub async fn run(mut client: Client, mut path: Option<PathBuf>) {
+	let mut conv = match &path {
+		Some(p) if p.exists() => load(p).unwrap_or_else(|e| panic!("{e}")),
+		_ => Conversation::new(),
+	};
+	let mut session_cost_cents = 0.0;
+	let mut session_usage = Usage::default();
+	let mut editor = DefaultEditor::new().expect("Failed to initialize line editor");
+	println!("Type /help for commands.");
+
+	loop {
+		let line = match editor.readline("> ") {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted) => continue,
+			Err(ReadlineError::Eof) => break,
+			Err(e) => panic!("Failed to read input: {e}"),
+		};
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+		let _ = editor.add_history_entry(line);
+
+		if let Some(command) = line.strip_prefix('/') {
+			let (command, arg) = command.split_once(' ').map_or((command, ""), |(c, a)| (c, a.trim()));
+			match (command, arg) {
+				("help", _) => println!("{HELP}"),
+				("quit" | "exit", _) => break,
+				("model", arg) => match arg.parse::<Model>() {
+					Ok(model) => client = client.model(model),
+					Err(_) => eprintln!("Unknown model: {arg}"),
+				},
+				("thinking", arg) => match arg.parse::<ThinkingLevel>() {
+					Ok(level) => client = client.thinking(level),
+					Err(_) => eprintln!("Unknown thinking level: {arg}"),
+				},
+				("attach", "") => eprintln!("Usage: /attach <path>"),
+				("attach", arg) => match Message::new_with_file_from_path(Role::User, arg) {
+					Ok(message) => conv.0.push(message),
+					Err(e) => eprintln!("{e}"),
+				},
+				("save", arg) => {
+					if !arg.is_empty() {
+						path = Some(PathBuf::from(arg));
+					}
+					match &path {
+						Some(p) => match save(&conv, p) {
+							Ok(()) => println!("Saved to {}", p.display()),
+							Err(e) => eprintln!("{e}"),
+						},
+						None => eprintln!("Usage: /save <path>"),
+					}
+				}
+				("load", "") => eprintln!("Usage: /load <path>"),
+				("load", arg) => match load(Path::new(arg)) {
+					Ok(loaded) => {
+						conv = loaded;
+						path = Some(PathBuf::from(arg));
+						println!("Loaded {} messages", conv.0.len());
+					}
+					Err(e) => eprintln!("{e}"),
+				},
+				("clear", _) => conv = Conversation::new(),
+				("cost", _) => println!(
+					"{session_cost_cents:.4}¢ | tokens: {} in / {} out",
+					session_usage.input + session_usage.cache_read + session_usage.cache_write,
+					session_usage.output
+				),
+				_ => eprintln!("Unknown command: /{command}, see /help"),
+			}
+			continue;
+		}
+
+		conv.add(Role::User, line);
+		let result = client
+			.conversation_streamed(&conv, |event| match event {
+				StreamEvent::Text(text) => {
+					print!("{text}");
+					let _ = std::io::stdout().flush();
+				}
+				StreamEvent::Thinking(thinking) => {
+					print!("\x1b[2m{thinking}\x1b[0m");
+					let _ = std::io::stdout().flush();
+				}
+				_ => {}
+			})
+			.await;
+		match result {
+			Ok(response) => {
+				println!("\n\x1b[2m{response}\x1b[0m");
+				session_cost_cents += response.cost_cents;
+				session_usage += response.usage;
+				conv.add_response(&response);
+			}
+			Err(e) => {
+				eprintln!("\n{e}");
+				conv.0.pop();
+			}
+		}
+	}
+}
+
+pub fn load(path: &Path) -> Result<Conversation, String> {
+	let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+	serde_json::from_str(&json).map_err(|e| format!("Failed to parse {}: {e}", path.display()))
+}
+
+pub fn save(conv: &Conversation, path: &Path) -> Result<(), String> {
+	std::fs::write(path, serde_json::to_string_pretty(conv).unwrap()).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
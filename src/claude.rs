@@ -1,69 +1,150 @@
-use std::str::FromStr as _;
-
-use eyre::{Result, bail};
 use futures::stream::StreamExt;
 use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 
-use crate::{Backend, Conversation, FileAttachment, Request, Response, Role, ThinkingLevel};
+use crate::{Backend, Conversation, Error, FileAttachment, Request, Response, ResponseStream, Result, Role, StreamEvent, ThinkingBlock, ThinkingLevel, ToolCall, Usage};
 
 pub struct Cost {
 	pub million_input_tokens: f32,
 	pub million_output_tokens: f32,
+	/// 1.25x input, for the default 5 minute cache
+	pub million_cache_write_tokens: f32,
+	/// 0.1x input
+	pub million_cache_read_tokens: f32,
 }
 pub(crate) struct Claude {
-	pub api_key: String,
+	pub api_key: Option<String>,
+	/// Sent as is; [ClaudeModel] only decides pricing and output limits
+	pub model_id: String,
 	pub model: ClaudeModel,
+	/// Messages endpoint, eg `https://api.anthropic.com/v1/messages`
+	pub url: String,
 }
 impl Claude {
 	///docs: https://docs.claude.com/claude/reference/messages_post
 	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
-		let mut conversation = ClaudeConversation::from(request.conversation);
+		let streaming = !matches!(request.max_tokens, Some(max_tokens) if max_tokens <= 4096);
+		let request_builder = self.request_builder(request, streaming)?;
 
-		// Prepend files to the first user message
-		if !request.files.is_empty() {
-			if let Some(first_user_msg) = conversation.messages.iter_mut().find(|m| m.role == "user") {
-				let mut file_blocks: Vec<ClaudeContentBlock> = request.files.iter().map(|f| file_to_content_block(f)).collect();
-
-				// Convert existing content to blocks and prepend file blocks
-				match &first_user_msg.content {
-					ClaudeMessageContent::Text(text) => {
-						file_blocks.push(ClaudeContentBlock::Text { text: text.clone() });
-						first_user_msg.content = ClaudeMessageContent::ContentBlocks(file_blocks);
-					}
-					ClaudeMessageContent::ContentBlocks(existing_blocks) => {
-						file_blocks.extend(existing_blocks.clone());
-						first_user_msg.content = ClaudeMessageContent::ContentBlocks(file_blocks);
-					}
-				}
+		let mut response = match streaming {
+			false => {
+				tracing::info!("getting through a rest get");
+				rest_g(request_builder).await?
 			}
+			true => {
+				tracing::info!("getting through a stream");
+				stream(request_builder).await?
+			}
+		};
+
+		// Prepend the "{" we used for prefilling when force_json was enabled
+		if prefills_json(request) {
+			response.text = format!("{{{}", response.text);
 		}
 
-		let url = "https://api.anthropic.com/v1/messages";
+		response.cost_cents = self.model.cost_cents(&response.usage);
+		response.model = self.model_id.clone();
+		response.thinking = request.thinking;
+		Ok(response)
+	}
+
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		let response = send(self.request_builder(request, true)?).await?;
+		let events = sse_events(response.bytes_stream().boxed());
+		// Surface the "{" we used for prefilling when force_json was enabled
+		Ok(match prefills_json(request) {
+			true => Box::pin(futures::stream::once(async { Ok(StreamEvent::Text("{".to_string())) }).chain(events)),
+			false => events,
+		})
+	}
+
+	fn request_builder(&self, request: &Request<'_>, stream: bool) -> Result<reqwest::RequestBuilder> {
+		let headers = self.headers()?;
+		let payload = self.payload(request, stream)?;
+		tracing::debug!(?payload);
+		Ok(reqwest::Client::new().post(&self.url).headers(headers).json(&payload))
+	}
 
-		// Header {{{
+	///docs: https://docs.claude.com/en/api/messages-count-tokens
+	async fn do_count_tokens(&self, request: &Request<'_>) -> Result<u32> {
+		#[derive(Deserialize)]
+		struct Count {
+			input_tokens: u32,
+		}
+		let headers = self.headers()?;
+		let mut payload = self.payload(request, false)?;
+		// the endpoint rejects sampling parameters
+		payload
+			.as_object_mut()
+			.unwrap()
+			.retain(|key, _| matches!(key.as_str(), "model" | "messages" | "system" | "tools" | "thinking"));
+		let response = send(reqwest::Client::new().post(format!("{}/count_tokens", self.url)).headers(headers).json(&payload)).await?;
+		Ok(response.json::<Count>().await?.input_tokens)
+	}
+
+	fn headers(&self) -> Result<HeaderMap> {
+		let api_key = self.api_key.as_deref().ok_or(Error::MissingCredentials { env_var: "CLAUDE_TOKEN" })?;
 		let mut headers = HeaderMap::new();
-		headers.insert("x-api-key", HeaderValue::from_str(&self.api_key).unwrap());
+		headers.insert(
+			"x-api-key",
+			HeaderValue::from_str(api_key).map_err(|_| Error::Config("Claude API key is not a valid header value".to_string()))?,
+		);
 		headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01")); // API standard edition, does not influence model versions
 		headers.insert("anthropic-beta", HeaderValue::from_static("output-128k-2025-02-19,structured-outputs-2025-11-13"));
 		headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-		//,}}}
+		Ok(headers)
+	}
+
+	/// Output tokens `request` reserves, capped at what the model can produce.
+	fn max_tokens(&self, request: &Request<'_>) -> usize {
+		match request.max_tokens {
+			Some(max_tokens) => max_tokens.min(self.model.max_tokens()),
+			_ => self.model.max_tokens(),
+		}
+	}
 
-		let request_builder = reqwest::Client::new().post(url).headers(headers);
+	fn payload(&self, request: &Request<'_>, stream: bool) -> Result<Value> {
+		let mut conversation = ClaudeConversation::from(request.conversation);
 
-		let system_message = match conversation.messages[0].role == "system" {
-			true => {
-				let system_message = conversation.messages.remove(0);
-				Some(system_message.content)
+		// Prepend files to the first user message
+		let mut file_breakpoint = None;
+		if !request.files.is_empty()
+			&& let Some((i, first_user_msg)) = conversation.messages.iter_mut().enumerate().find(|(_, m)| m.role == "user")
+		{
+			file_breakpoint = request.files.iter().rposition(|f| f.cache).map(|block| (i, block));
+			let mut file_blocks: Vec<ClaudeContentBlock> = request.files.iter().map(file_to_content_block).collect();
+
+			// Convert existing content to blocks and prepend file blocks
+			match &first_user_msg.content {
+				ClaudeMessageContent::Text(text) => {
+					file_blocks.push(ClaudeContentBlock::Text { text: text.clone() });
+					first_user_msg.content = ClaudeMessageContent::ContentBlocks(file_blocks);
+				}
+				ClaudeMessageContent::ContentBlocks(existing_blocks) => {
+					file_blocks.extend(existing_blocks.clone());
+					first_user_msg.content = ClaudeMessageContent::ContentBlocks(file_blocks);
+				}
 			}
+		}
+
+		let system_message = match conversation.messages.first().is_some_and(|m| m.role == "system") {
+			true => Some(conversation.messages.remove(0)),
 			false => None,
 		};
+		if conversation.messages.is_empty() {
+			return Err(Error::Config("Claude needs at least one message besides the system prompt".to_string()));
+		}
+		// Removing the system message shifted the rest
+		let file_breakpoint = file_breakpoint.map(|(i, block)| (i - system_message.is_some() as usize, block));
+		let mut breakpoints = Vec::new();
+		if system_message.as_ref().is_some_and(|m| m.cache) {
+			breakpoints.push(CacheBreakpoint::System);
+		}
+		breakpoints.extend(file_breakpoint.map(|(i, block)| CacheBreakpoint::Block(i, block)));
+		breakpoints.extend(conversation.messages.iter().enumerate().filter(|(_, m)| m.cache).map(|(i, _)| CacheBreakpoint::Message(i)));
 
-		let max_tokens = match request.max_tokens {
-			Some(max_tokens) => max_tokens.min(self.model.max_tokens()),
-			_ => self.model.max_tokens(),
-		};
+		let max_tokens = self.max_tokens(request);
 
 		// Payload {{{
 		let thinking_budget = match request.thinking {
@@ -78,10 +159,11 @@ impl Claude {
 			None => request.temperature.unwrap_or(0.0),
 		};
 		let mut payload = json!({
-			"model": self.model.to_str(),
+			"model": self.model_id,
 			"temperature": temperature,
 			"max_tokens": max_tokens,
-			"messages": conversation.messages
+			"messages": conversation.messages,
+			"stream": stream,
 		});
 		if let Some(budget) = thinking_budget {
 			payload
@@ -93,43 +175,117 @@ impl Claude {
 			payload.as_object_mut().unwrap().insert("stop_sequences".to_string(), serde_json::json!(stop_seqs));
 		}
 		if let Some(system_message) = system_message {
-			payload.as_object_mut().unwrap().insert("system".to_string(), serde_json::json!(system_message));
+			payload.as_object_mut().unwrap().insert("system".to_string(), serde_json::json!(system_message.content));
+		}
+		if !request.tools.is_empty() {
+			let tools: Vec<Value> = request
+				.tools
+				.iter()
+				.map(|t| json!({"name": t.name, "description": t.description, "input_schema": t.input_schema}))
+				.collect();
+			payload.as_object_mut().unwrap().insert("tools".to_string(), json!(tools));
+		}
+		if let Some(schema) = request.json_schema {
+			payload
+				.as_object_mut()
+				.unwrap()
+				.insert("output_format".to_string(), json!({"type": "json_schema", "schema": strict_schema(schema)}));
 		}
-		if request.force_json {
+		if prefills_json(request) {
 			// Use prefill approach - Claude's structured outputs require strict schemas with
 			// additionalProperties: false, which doesn't work for generic JSON responses.
 			// Prefill works universally across all models.
 			conversation.messages.push(ClaudeMessage {
 				role: "assistant",
 				content: ClaudeMessageContent::Text("{".to_string()),
+				cache: false,
 			});
 			payload.as_object_mut().unwrap().insert("messages".to_string(), serde_json::json!(conversation.messages));
 		}
+		mark_cache_breakpoints(&mut payload, breakpoints);
 		//,}}}
 
-		let mut response = match request.max_tokens {
-			Some(max_tokens) if max_tokens <= 4096 => {
-				payload.as_object_mut().unwrap().insert("stream".to_owned(), serde_json::json!(false));
-				tracing::info!("getting through a rest get");
-				tracing::debug!(?payload);
-				rest_g(request_builder.json(&payload)).await?
-			}
-			_ => {
-				payload.as_object_mut().unwrap().insert("stream".to_owned(), serde_json::json!(true));
-				tracing::info!("getting through a stream");
-				tracing::debug!(?payload);
-				stream(request_builder.json(&payload), &self.model).await?
-			}
-		};
+		Ok(payload)
+	}
+}
 
-		// Prepend the "{" we used for prefilling when force_json was enabled
-		if request.force_json {
-			response.text = format!("{{{}", response.text);
+/// Where a `cache_control` marker goes. The prompt prefix ending at each marked block is cached.
+enum CacheBreakpoint {
+	System,
+	/// Last block of the message
+	Message(usize),
+	/// Specific block of a message
+	Block(usize, usize),
+}
+
+fn mark_cache_breakpoints(payload: &mut Value, mut breakpoints: Vec<CacheBreakpoint>) {
+	const MAX_BREAKPOINTS: usize = 4;
+	if breakpoints.len() > MAX_BREAKPOINTS {
+		tracing::warn!("{} cache breakpoints requested, Claude allows {MAX_BREAKPOINTS}; keeping the last ones", breakpoints.len());
+		breakpoints.drain(..breakpoints.len() - MAX_BREAKPOINTS);
+	}
+	for breakpoint in breakpoints {
+		let block = match breakpoint {
+			CacheBreakpoint::System => last_block(&mut payload["system"]),
+			CacheBreakpoint::Message(i) => last_block(&mut payload["messages"][i]["content"]),
+			CacheBreakpoint::Block(i, block) => payload["messages"][i]["content"].get_mut(block),
+		};
+		if let Some(block) = block {
+			block["cache_control"] = json!({"type": "ephemeral"});
 		}
+	}
+}
 
-		response.model = self.model.to_str().to_string();
-		response.thinking = request.thinking;
-		Ok(response)
+/// Plain text content is sent as a bare string, which can't carry `cache_control`, so it's turned into a text block first.
+fn last_block(content: &mut Value) -> Option<&mut Value> {
+	if let Value::String(text) = content {
+		*content = json!([{"type": "text", "text": text}]);
+	}
+	content.as_array_mut()?.last_mut()
+}
+
+/// Schema-constrained requests get structured outputs instead, which don't allow prefilling.
+/// Neither is needed when the conversation already ends in a prefill.
+fn prefills_json(request: &Request<'_>) -> bool {
+	request.force_json && request.json_schema.is_none() && !ends_in_prefill(request)
+}
+
+fn ends_in_prefill(request: &Request<'_>) -> bool {
+	request.conversation.0.last().is_some_and(|m| matches!(m.role, Role::Assistant))
+}
+
+/// Adapt a generated schema to what structured outputs accept: every object closed with `additionalProperties: false`,
+/// no numeric/length constraints and only the supported string formats. Dropped constraints are still enforced on deserialization.
+fn strict_schema(schema: &Value) -> Value {
+	const SUPPORTED_FORMATS: [&str; 9] = ["date-time", "time", "date", "duration", "email", "hostname", "uri", "ipv4", "ipv6"];
+	const UNSUPPORTED_KEYWORDS: [&str; 8] = ["$schema", "minimum", "maximum", "exclusiveMinimum", "exclusiveMaximum", "multipleOf", "minLength", "maxLength"];
+	match schema {
+		Value::Object(map) => {
+			let mut out = serde_json::Map::new();
+			for (key, value) in map {
+				match key.as_str() {
+					// Keys of these are property/definition names, not keywords
+					"properties" | "$defs" | "definitions" => {
+						let named = value
+							.as_object()
+							.map(|m| m.iter().map(|(name, s)| (name.clone(), strict_schema(s))).collect())
+							.unwrap_or_default();
+						out.insert(key.clone(), Value::Object(named));
+					}
+					"format" if !value.as_str().is_some_and(|f| SUPPORTED_FORMATS.contains(&f)) => {}
+					k if UNSUPPORTED_KEYWORDS.contains(&k) => {}
+					_ => {
+						out.insert(key.clone(), strict_schema(value));
+					}
+				}
+			}
+			if out.get("type").and_then(Value::as_str) == Some("object") || out.contains_key("properties") {
+				out.insert("additionalProperties".to_string(), Value::Bool(false));
+			}
+			Value::Object(out)
+		}
+		Value::Array(items) => Value::Array(items.iter().map(strict_schema).collect()),
+		other => other.clone(),
 	}
 }
 
@@ -137,6 +293,35 @@ impl Backend for Claude {
 	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
 		Box::pin(self.do_conversation(request))
 	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		format!("claude:{}", self.model_id)
+	}
+
+	fn cost_cents(&self, usage: &Usage) -> f32 {
+		self.model.cost_cents(usage)
+	}
+
+	/// Except with extended thinking or structured outputs, which reject it.
+	fn native_prefill(&self, request: &Request<'_>) -> bool {
+		matches!(request.thinking, ThinkingLevel::None) && request.json_schema.is_none()
+	}
+
+	fn count_tokens<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<u32>> + Send + 'a>> {
+		Box::pin(self.do_count_tokens(request))
+	}
+
+	fn max_output(&self, request: &Request<'_>) -> Option<u32> {
+		Some(self.max_tokens(request) as u32)
+	}
+
+	fn input_limit(&self, request: &Request<'_>) -> Option<u32> {
+		Some(self.model.context_window().saturating_sub(self.max_tokens(request) as u32))
+	}
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -147,32 +332,39 @@ pub(crate) enum ClaudeModel {
 	Opus41,
 }
 impl ClaudeModel {
-	fn to_str(&self) -> &str {
-		match self {
-			ClaudeModel::Haiku45 => "claude-haiku-4-5",
-			ClaudeModel::Sonnet45 => "claude-sonnet-4-5",
-			ClaudeModel::Opus41 => "claude-opus-4-1",
-		}
-	}
-
 	///NB: could end up being outdated, as I freely use "-latest" marker in model defs
 	pub fn cost(&self) -> Cost {
 		match self {
 			Self::Haiku45 => Cost {
 				million_input_tokens: 1.0,
 				million_output_tokens: 5.0,
+				million_cache_write_tokens: 1.25,
+				million_cache_read_tokens: 0.1,
 			},
 			Self::Sonnet45 => Cost {
 				million_input_tokens: 3.0,
 				million_output_tokens: 15.0,
+				million_cache_write_tokens: 3.75,
+				million_cache_read_tokens: 0.3,
 			},
 			Self::Opus41 => Cost {
 				million_input_tokens: 15.0,
 				million_output_tokens: 75.0,
+				million_cache_write_tokens: 18.75,
+				million_cache_read_tokens: 1.5,
 			},
 		}
 	}
 
+	pub fn cost_cents(&self, usage: &Usage) -> f32 {
+		let cost = self.cost();
+		(usage.input as f32 * cost.million_input_tokens
+			+ usage.cache_write as f32 * cost.million_cache_write_tokens
+			+ usage.cache_read as f32 * cost.million_cache_read_tokens
+			+ usage.output as f32 * cost.million_output_tokens)
+			/ 10_000.0
+	}
+
 	pub fn max_tokens(&self) -> usize {
 		match self {
 			Self::Haiku45 => 64_000,
@@ -180,16 +372,25 @@ impl ClaudeModel {
 			Self::Opus41 => 32_000,
 		}
 	}
+
+	/// Input and output tokens together. Sonnet's 1M window is a beta we don't opt into.
+	pub fn context_window(&self) -> u32 {
+		match self {
+			Self::Haiku45 => 200_000,
+			Self::Sonnet45 => 200_000,
+			Self::Opus41 => 200_000,
+		}
+	}
 }
 impl std::str::FromStr for ClaudeModel {
-	type Err = eyre::Report;
+	type Err = Error;
 
 	fn from_str(s: &str) -> Result<Self> {
 		Ok(match s {
 			_ if s.to_lowercase().contains("haiku") => Self::Haiku45,
 			_ if s.to_lowercase().contains("sonnet") => Self::Sonnet45,
 			_ if s.to_lowercase().contains("opus") => Self::Opus41,
-			_ => bail!("Unknown model: {s}"),
+			_ => return Err(Error::Config(format!("Unknown model: {s}"))),
 		})
 	}
 }
@@ -210,6 +411,14 @@ enum ClaudeContentBlock {
 	Image { source: ImageSource },
 	#[serde(rename = "document")]
 	Document { source: DocumentSource },
+	#[serde(rename = "tool_use")]
+	ToolUse { id: String, name: String, input: Value },
+	#[serde(rename = "tool_result")]
+	ToolResult { tool_use_id: String, content: String, is_error: bool },
+	#[serde(rename = "thinking")]
+	Thinking { thinking: String, signature: String },
+	#[serde(rename = "redacted_thinking")]
+	RedactedThinking { data: String },
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -232,6 +441,8 @@ struct DocumentSource {
 struct ClaudeMessage {
 	role: &'static str,
 	content: ClaudeMessageContent,
+	#[serde(skip)]
+	cache: bool,
 }
 #[derive(Debug, Serialize)]
 struct ClaudeConversation {
@@ -300,14 +511,65 @@ impl From<&Conversation> for ClaudeConversation {
 						.collect();
 					ClaudeMessageContent::ContentBlocks(blocks)
 				}
+				MessageContent::ToolUse { text, calls } => {
+					let mut blocks = Vec::new();
+					if !text.is_empty() {
+						blocks.push(ClaudeContentBlock::Text { text: text.clone() });
+					}
+					blocks.extend(calls.iter().map(|call| ClaudeContentBlock::ToolUse {
+						id: call.id.clone(),
+						name: call.name.clone(),
+						input: call.input.clone(),
+					}));
+					ClaudeMessageContent::ContentBlocks(blocks)
+				}
+				MessageContent::ToolResults(results) => ClaudeMessageContent::ContentBlocks(
+					results
+						.iter()
+						.map(|r| ClaudeContentBlock::ToolResult {
+							tool_use_id: r.tool_use_id.clone(),
+							content: r.content.clone(),
+							is_error: r.is_error,
+						})
+						.collect(),
+				),
 			};
 
-			messages.push(ClaudeMessage { role, content });
+			let content = match message.thinking.is_empty() {
+				true => content,
+				false => with_thinking(&message.thinking, content),
+			};
+			messages.push(ClaudeMessage {
+				role,
+				content,
+				cache: message.cache,
+			});
 		}
 		Self { messages }
 	}
 }
 
+/// Thinking blocks go first in the assistant turn. Ones without a signature came from another provider and would be rejected.
+fn with_thinking(thinking: &[ThinkingBlock], content: ClaudeMessageContent) -> ClaudeMessageContent {
+	let mut blocks: Vec<ClaudeContentBlock> = thinking
+		.iter()
+		.filter_map(|block| match block {
+			ThinkingBlock::Thinking { text, signature: Some(signature) } => Some(ClaudeContentBlock::Thinking {
+				thinking: text.clone(),
+				signature: signature.clone(),
+			}),
+			ThinkingBlock::Thinking { signature: None, .. } => None,
+			ThinkingBlock::Redacted { data } => Some(ClaudeContentBlock::RedactedThinking { data: data.clone() }),
+		})
+		.collect();
+	match content {
+		ClaudeMessageContent::Text(text) if text.is_empty() => {}
+		ClaudeMessageContent::Text(text) => blocks.push(ClaudeContentBlock::Text { text }),
+		ClaudeMessageContent::ContentBlocks(content_blocks) => blocks.extend(content_blocks),
+	}
+	ClaudeMessageContent::ContentBlocks(blocks)
+}
+
 /// Convert a file attachment to the appropriate content block.
 /// PDFs use the document block, text-based files are decoded and inserted as text.
 fn file_to_content_block(file: &FileAttachment) -> ClaudeContentBlock {
@@ -340,85 +602,330 @@ fn file_to_content_block(file: &FileAttachment) -> ClaudeContentBlock {
 	}
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
-struct ClaudeContent {
-	#[serde(rename = "type")]
-	content_type: String,
-	text: String,
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClaudeContent {
+	Text {
+		text: String,
+	},
+	ToolUse {
+		id: String,
+		name: String,
+		input: Value,
+	},
+	Thinking {
+		thinking: String,
+		signature: String,
+	},
+	RedactedThinking {
+		data: String,
+	},
+	#[serde(other)]
+	Other,
 }
 #[derive(Debug, Deserialize)]
 struct ClaudeUsage {
 	input_tokens: u32,
 	output_tokens: u32,
+	#[serde(default)]
+	cache_creation_input_tokens: u32,
+	#[serde(default)]
+	cache_read_input_tokens: u32,
 }
-
-// stream {{{
-async fn stream(request_builder: reqwest::RequestBuilder, model: &ClaudeModel) -> Result<Response> {
-	#[derive(Debug, Deserialize, Serialize)]
-	struct Delta {
-		text: String,
-		#[serde(rename = "type")]
-		delta_type: String,
+impl From<&ClaudeUsage> for Usage {
+	fn from(usage: &ClaudeUsage) -> Self {
+		Self {
+			input: usage.input_tokens,
+			output: usage.output_tokens,
+			cache_read: usage.cache_read_input_tokens,
+			cache_write: usage.cache_creation_input_tokens,
+			// Included in `output_tokens`, not reported separately
+			thinking: 0,
+		}
 	}
-	#[derive(Debug, Deserialize, Serialize)]
-	struct DeltaContentBlock {
-		delta: Delta,
-		index: u32,
-		#[serde(rename = "type")]
-		response_type: String,
+}
+
+/// Send the request, turning non-success statuses into the matching [Error].
+async fn send(request_builder: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+	let response = request_builder.send().await?;
+	match response.status().is_success() {
+		true => Ok(response),
+		false => Err(Error::from_response(response).await),
 	}
+}
 
+// stream {{{
+async fn stream(request_builder: reqwest::RequestBuilder) -> Result<Response> {
 	let ttfb_start = std::time::Instant::now();
-	let mut response_stream = request_builder.send().await?.bytes_stream();
+	let mut events = sse_events(send(request_builder).await?.bytes_stream().boxed());
 	let ttfb = ttfb_start.elapsed();
 
 	let mut accumulated_message = String::new();
-
-	fn parse_sse(bytes: bytes::Bytes) -> String {
-		let s = String::from_utf8(bytes.to_vec()).expect("Found invalid UTF-8");
-		let mut parsed_string = String::new();
-
-		let split = s
-			.split("event: content_block_delta\ndata: ")
-			.map(|s| s.split("\n\nevent: ").collect::<Vec<&str>>().get(0).unwrap().to_string())
-			.collect::<Vec<String>>();
-
-		for s in split {
-			if let Ok(v) = serde_json::from_str::<DeltaContentBlock>(&s)
-				&& (v.response_type == "content_block_delta" || v.delta.delta_type == "text_delta")
-			{
-				parsed_string.push_str(&v.delta.text);
+	let mut tool_calls = Vec::new();
+	let mut thinking_blocks = Vec::new();
+	let mut usage = Usage::default();
+	let mut stop = (None, None);
+	while let Some(event) = events.next().await {
+		match event? {
+			StreamEvent::Text(text) => {
+				tracing::debug!(text);
+				accumulated_message.push_str(&text);
 			}
+			StreamEvent::ToolUse(call) => tool_calls.push(call),
+			StreamEvent::ThinkingBlock(block) => thinking_blocks.push(block),
+			StreamEvent::Usage(u) => usage = u,
+			StreamEvent::Stop { reason, stop_sequence } => stop = (reason, stop_sequence),
+			_ => {}
 		}
-		parsed_string
 	}
 
-	while let Some(events_batch) = response_stream.next().await {
-		let events_batch = events_batch?;
-
-		let parsed = parse_sse(events_batch);
-		tracing::debug!(parsed);
-		accumulated_message.push_str(&parsed);
-	}
-
-	let estimated_tokens = accumulated_message.split_whitespace().count() as f32 * 0.7;
-	let cost = (model.cost().million_output_tokens * estimated_tokens) / 1_000_000.0;
 	Ok(Response {
 		text: accumulated_message,
-		cost_cents: cost,
+		cost_cents: 0.0,
+		usage,
 		duration: std::time::Duration::ZERO,
 		overhead: ttfb,
 		model: String::new(),
 		thinking: ThinkingLevel::None,
+		tool_calls,
+		thinking_blocks,
+		stop_reason: stop.0,
+		stop_sequence: stop.1,
+		..
 	})
 }
+
+/// Turn the body of a streaming messages response into [StreamEvent]s.
+fn sse_events(bytes: futures::stream::BoxStream<'static, reqwest::Result<bytes::Bytes>>) -> ResponseStream {
+	#[derive(Debug, Deserialize)]
+	#[serde(tag = "type", rename_all = "snake_case")]
+	enum SseEvent {
+		MessageStart {
+			message: MessageStart,
+		},
+		ContentBlockStart {
+			index: usize,
+			content_block: StartBlock,
+		},
+		ContentBlockDelta {
+			index: usize,
+			delta: BlockDelta,
+		},
+		ContentBlockStop {
+			index: usize,
+		},
+		MessageDelta {
+			delta: MessageDelta,
+			usage: Option<DeltaUsage>,
+		},
+		/// Failure after the 200 was sent, eg `overloaded_error`
+		Error {
+			error: StreamError,
+		},
+		#[serde(other)]
+		Other,
+	}
+	#[derive(Debug, Deserialize)]
+	struct StreamError {
+		#[serde(rename = "type")]
+		kind: String,
+		message: String,
+	}
+	#[derive(Debug, Deserialize)]
+	struct MessageStart {
+		usage: ClaudeUsage,
+	}
+	#[derive(Debug, Deserialize)]
+	#[serde(tag = "type", rename_all = "snake_case")]
+	enum StartBlock {
+		ToolUse {
+			id: String,
+			name: String,
+		},
+		Thinking,
+		RedactedThinking {
+			data: String,
+		},
+		#[serde(other)]
+		Other,
+	}
+	#[derive(Debug, Deserialize)]
+	#[serde(tag = "type", rename_all = "snake_case")]
+	enum BlockDelta {
+		TextDelta {
+			text: String,
+		},
+		ThinkingDelta {
+			thinking: String,
+		},
+		InputJsonDelta {
+			partial_json: String,
+		},
+		/// Sent once, right before the thinking block stops
+		SignatureDelta {
+			signature: String,
+		},
+		#[serde(other)]
+		Other,
+	}
+	/// Tool input arrives as json fragments spread over several deltas
+	struct PartialToolUse {
+		index: usize,
+		id: String,
+		name: String,
+		input_json: String,
+	}
+	/// Kept whole so it can be sent back with the next turn
+	struct PartialThinking {
+		index: usize,
+		text: String,
+		signature: String,
+	}
+	#[derive(Debug, Deserialize)]
+	struct MessageDelta {
+		stop_reason: Option<String>,
+		stop_sequence: Option<String>,
+	}
+	/// Cumulative; input counts are only present on newer API versions
+	#[derive(Debug, Deserialize)]
+	struct DeltaUsage {
+		output_tokens: u32,
+		input_tokens: Option<u32>,
+		cache_creation_input_tokens: Option<u32>,
+		cache_read_input_tokens: Option<u32>,
+	}
+
+	struct State {
+		usage: Usage,
+		tool_uses: Vec<PartialToolUse>,
+		thinking: Vec<PartialThinking>,
+	}
+	impl crate::sse::Translate for State {
+		fn event(&mut self, raw: crate::sse::Event, out: &mut Vec<StreamEvent>) -> Result<()> {
+			let event = match serde_json::from_str::<SseEvent>(&raw.data) {
+				Ok(event) => event,
+				Err(e) => {
+					tracing::warn!("Failed to parse Claude stream event: {e}\n{}", raw.data);
+					return Ok(());
+				}
+			};
+			match event {
+				SseEvent::MessageStart { message } => {
+					self.usage = Usage::from(&message.usage);
+					out.push(StreamEvent::Usage(self.usage));
+				}
+				SseEvent::ContentBlockStart { index, content_block } => match content_block {
+					StartBlock::ToolUse { id, name } => self.tool_uses.push(PartialToolUse {
+						index,
+						id,
+						name,
+						input_json: String::new(),
+					}),
+					StartBlock::Thinking => self.thinking.push(PartialThinking {
+						index,
+						text: String::new(),
+						signature: String::new(),
+					}),
+					StartBlock::RedactedThinking { data } => out.push(StreamEvent::ThinkingBlock(ThinkingBlock::Redacted { data })),
+					StartBlock::Other => {}
+				},
+				SseEvent::ContentBlockDelta { index, delta } => match delta {
+					BlockDelta::TextDelta { text } => out.push(StreamEvent::Text(text)),
+					BlockDelta::ThinkingDelta { thinking } => {
+						if let Some(partial) = self.thinking.iter_mut().find(|t| t.index == index) {
+							partial.text.push_str(&thinking);
+						}
+						out.push(StreamEvent::Thinking(thinking));
+					}
+					BlockDelta::SignatureDelta { signature } => {
+						if let Some(partial) = self.thinking.iter_mut().find(|t| t.index == index) {
+							partial.signature.push_str(&signature);
+						}
+					}
+					BlockDelta::InputJsonDelta { partial_json } => {
+						if let Some(tool_use) = self.tool_uses.iter_mut().find(|t| t.index == index) {
+							tool_use.input_json.push_str(&partial_json);
+						}
+					}
+					BlockDelta::Other => {}
+				},
+				SseEvent::ContentBlockStop { index } => {
+					if let Some(pos) = self.thinking.iter().position(|t| t.index == index) {
+						let partial = self.thinking.remove(pos);
+						out.push(StreamEvent::ThinkingBlock(ThinkingBlock::Thinking {
+							text: partial.text,
+							signature: Some(partial.signature).filter(|s| !s.is_empty()),
+						}));
+					}
+					if let Some(pos) = self.tool_uses.iter().position(|t| t.index == index) {
+						let tool_use = self.tool_uses.remove(pos);
+						// Tools without parameters stream no input at all
+						let input = match tool_use.input_json.is_empty() {
+							true => Value::Object(Default::default()),
+							false => serde_json::from_str(&tool_use.input_json).unwrap_or_else(|e| {
+								tracing::warn!("Failed to parse input of tool call {}: {e}", tool_use.name);
+								Value::String(tool_use.input_json.clone())
+							}),
+						};
+						out.push(StreamEvent::ToolUse(ToolCall {
+							id: tool_use.id,
+							name: tool_use.name,
+							input,
+						}));
+					}
+				}
+				SseEvent::MessageDelta { delta, usage } => {
+					if let Some(usage) = usage {
+						self.usage.output = usage.output_tokens;
+						self.usage.input = usage.input_tokens.unwrap_or(self.usage.input);
+						self.usage.cache_write = usage.cache_creation_input_tokens.unwrap_or(self.usage.cache_write);
+						self.usage.cache_read = usage.cache_read_input_tokens.unwrap_or(self.usage.cache_read);
+						out.push(StreamEvent::Usage(self.usage));
+					}
+					if delta.stop_reason.as_deref() == Some("refusal") {
+						return Err(Error::Refusal("This may be due to content policy restrictions.".to_string()));
+					}
+					out.push(StreamEvent::Stop {
+						reason: delta.stop_reason,
+						stop_sequence: delta.stop_sequence,
+					});
+				}
+				SseEvent::Error { error } => return Err(stream_error(&error.kind, error.message)),
+				SseEvent::Other => {}
+			}
+			Ok(())
+		}
+	}
+
+	let state = State {
+		usage: Usage::default(),
+		tool_uses: Vec::new(),
+		thinking: Vec::new(),
+	};
+	crate::sse::events(bytes, state)
+}
+
+/// Classify an `error` event by the HTTP status the same error gets before streaming starts.
+/// ref: https://docs.claude.com/en/api/errors
+fn stream_error(kind: &str, message: String) -> Error {
+	let status = match kind {
+		"invalid_request_error" => 400,
+		"authentication_error" => 401,
+		"permission_error" => 403,
+		"not_found_error" => 404,
+		"request_too_large" => 413,
+		"rate_limit_error" => 429,
+		"overloaded_error" => 529,
+		_ => 500,
+	};
+	Error::from_status(status, None, format!("{kind}: {message}"))
+}
 //,}}}
 
 // rest_g {{{
 async fn rest_g(request_builder: reqwest::RequestBuilder) -> Result<Response> {
 	let ttfb_start = std::time::Instant::now();
-	let value = request_builder.send().await?.json::<Value>().await?;
+	let value = send(request_builder).await?.json::<Value>().await?;
 	let ttfb = ttfb_start.elapsed();
 	tracing::debug!(?value);
 	let response = serde_json::from_value::<ClaudeResponse>(value.clone()).inspect_err(|e| {
@@ -430,7 +937,7 @@ async fn rest_g(request_builder: reqwest::RequestBuilder) -> Result<Response> {
 
 	// Check for refusal
 	if response.stop_reason == "refusal" {
-		bail!("Claude refused to process the request. This may be due to content policy restrictions.");
+		return Err(Error::Refusal("This may be due to content policy restrictions.".to_string()));
 	}
 
 	let mut resp: Response = response.into();
@@ -452,25 +959,60 @@ async fn rest_g(request_builder: reqwest::RequestBuilder) -> Result<Response> {
 	}
 	impl ClaudeResponse {
 		pub fn text(&self) -> String {
-			let contents = self.content.iter().filter(|c| c.content_type == "text").map(|c| c.text.to_owned()).collect::<Vec<String>>();
+			let contents = self
+				.content
+				.iter()
+				.filter_map(|c| match c {
+					ClaudeContent::Text { text } => Some(text.to_owned()),
+					_ => None,
+				})
+				.collect::<Vec<String>>();
 			contents.join("\n")
 		}
 
-		pub fn cost_cents(&self) -> f32 {
-			let model = ClaudeModel::from_str(&self.model).unwrap();
-			let cost = model.cost();
-			(self.usage.input_tokens as f32 * cost.million_input_tokens + self.usage.output_tokens as f32 * cost.million_output_tokens) / 10_000.0
+		pub fn tool_calls(&self) -> Vec<ToolCall> {
+			self.content
+				.iter()
+				.filter_map(|c| match c {
+					ClaudeContent::ToolUse { id, name, input } => Some(ToolCall {
+						id: id.clone(),
+						name: name.clone(),
+						input: input.clone(),
+					}),
+					_ => None,
+				})
+				.collect()
+		}
+
+		pub fn thinking_blocks(&self) -> Vec<ThinkingBlock> {
+			self.content
+				.iter()
+				.filter_map(|c| match c {
+					ClaudeContent::Thinking { thinking, signature } => Some(ThinkingBlock::Thinking {
+						text: thinking.clone(),
+						signature: Some(signature.clone()),
+					}),
+					ClaudeContent::RedactedThinking { data } => Some(ThinkingBlock::Redacted { data: data.clone() }),
+					_ => None,
+				})
+				.collect()
 		}
 	}
 	impl From<ClaudeResponse> for Response {
 		fn from(response: ClaudeResponse) -> Self {
 			Self {
 				text: response.text(),
-				cost_cents: response.cost_cents(),
+				cost_cents: 0.0,
+				usage: Usage::from(&response.usage),
 				duration: std::time::Duration::ZERO,
 				overhead: std::time::Duration::ZERO,
 				model: String::new(),
 				thinking: ThinkingLevel::None,
+				tool_calls: response.tool_calls(),
+				thinking_blocks: response.thinking_blocks(),
+				stop_reason: Some(response.stop_reason),
+				stop_sequence: response.stop_sequence,
+				..
 			}
 		}
 	}
@@ -479,6 +1021,187 @@ async fn rest_g(request_builder: reqwest::RequestBuilder) -> Result<Response> {
 
 #[cfg(test)]
 mod tests {
+	use super::*;
+	use crate::testing;
+
+	#[test]
+	fn cache_breakpoints_in_payload() {
+		let claude = Claude {
+			api_key: Some("test".to_string()),
+			model_id: "claude-haiku-4-5".to_string(),
+			model: ClaudeModel::Haiku45,
+			url: "http://localhost/v1/messages".to_string(),
+		};
+		let mut conv = Conversation::new_with_system("long instructions");
+		conv.cache_breakpoint();
+		conv.add(Role::User, "question");
+		let files = [FileAttachment {
+			base64_data: "aGk=".to_string(),
+			media_type: "application/pdf".to_string(),
+			cache: true,
+		}];
+		let request = Request {
+			files: &files,
+			..testing::request(&conv)
+		};
+		let built = claude.request_builder(&request, false).unwrap().build().unwrap();
+		let payload: Value = serde_json::from_slice(built.body().unwrap().as_bytes().unwrap()).unwrap();
+		let ephemeral = json!({"type": "ephemeral"});
+		assert_eq!(payload["system"][0]["text"], "long instructions");
+		assert_eq!(payload["system"][0]["cache_control"], ephemeral);
+		assert_eq!(payload["messages"][0]["content"][0]["cache_control"], ephemeral);
+		assert!(payload["messages"][0]["content"][1].get("cache_control").is_none());
+
+		for conv in [Conversation::new(), Conversation::new_with_system("long instructions")] {
+			let request = Request {
+				conversation: &conv,
+				stop_sequences: None,
+				..request
+			};
+			assert!(matches!(claude.payload(&request, false), Err(Error::Config(_))));
+		}
+	}
+
+	#[test]
+	fn thinking_blocks_sent_back() {
+		let response = Response {
+			text: "4".to_string(),
+			cost_cents: 0.0,
+			usage: Usage::default(),
+			duration: std::time::Duration::ZERO,
+			overhead: std::time::Duration::ZERO,
+			model: "claude-haiku-4-5".to_string(),
+			thinking: ThinkingLevel::Low,
+			thinking_blocks: vec![
+				ThinkingBlock::Thinking {
+					text: "2+2 is 4".to_string(),
+					signature: Some("sig".to_string()),
+				},
+				ThinkingBlock::Redacted { data: "opaque".to_string() },
+				ThinkingBlock::Thinking {
+					text: "from ollama".to_string(),
+					signature: None,
+				},
+			],
+			..
+		};
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "2+2?");
+		conv.add_response(&response);
+		let payload = serde_json::to_value(ClaudeConversation::from(&conv)).unwrap();
+		assert_eq!(
+			payload["messages"][1]["content"],
+			json!([
+				{"type": "thinking", "thinking": "2+2 is 4", "signature": "sig"},
+				{"type": "redacted_thinking", "data": "opaque"},
+				{"type": "text", "text": "4"}
+			])
+		);
+	}
+
+	#[tokio::test]
+	async fn sse_any_chunking() {
+		const STREAM: &str = concat!(
+			"event: message_start\r\n",
+			"data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\r\n\r\n",
+			"event: content_block_start\r\n",
+			"data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\r\n\r\n",
+			"event: ping\r\ndata: {\"type\":\"ping\"}\r\n\r\n",
+			"event: content_block_delta\r\n",
+			"data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Grüße, \"}}\r\n\r\n",
+			"event: content_block_delta\r\n",
+			"data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"世界\"}}\r\n\r\n",
+			"event: content_block_stop\r\ndata: {\"type\":\"content_block_stop\",\"index\":0}\r\n\r\n",
+			"event: content_block_start\r\n",
+			"data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"weather\",\"input\":{}}}\r\n\r\n",
+			"event: content_block_delta\r\n",
+			"data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\": \"}}\r\n\r\n",
+			"event: content_block_delta\r\n",
+			"data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"Zürich\\\"}\"}}\r\n\r\n",
+			"event: content_block_stop\r\ndata: {\"type\":\"content_block_stop\",\"index\":1}\r\n\r\n",
+			"event: message_delta\r\n",
+			"data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":30}}\r\n\r\n",
+			"event: message_stop\r\ndata: {\"type\":\"message_stop\"}",
+		);
+		async fn decode(chunks: Vec<&'static [u8]>) -> String {
+			let bytes = futures::stream::iter(chunks.into_iter().map(|chunk| Ok(bytes::Bytes::from_static(chunk))));
+			let events: Vec<StreamEvent> = sse_events(bytes.boxed()).map(Result::unwrap).collect().await;
+			format!("{events:?}")
+		}
+
+		let bytes = STREAM.as_bytes();
+		let expected = decode(vec![bytes]).await;
+		assert!(expected.contains(r#"Text("Grüße, ")"#) && expected.contains(r#"Text("世界")"#), "{expected}");
+		assert!(expected.contains(r#"name: "weather""#) && expected.contains("Zürich"), "{expected}");
+		assert!(
+			expected.contains("output: 30") && expected.ends_with(r#"Stop { reason: Some("tool_use"), stop_sequence: None }]"#),
+			"{expected}"
+		);
+		// every split point, which cuts through `\r\n`s and multi-byte characters
+		for i in 0..bytes.len() {
+			assert_eq!(decode(vec![&bytes[..i], &bytes[i..]]).await, expected, "split at {i}");
+		}
+		assert_eq!(decode(bytes.chunks(1).collect()).await, expected);
+	}
+
+	#[tokio::test]
+	async fn sse_stop_and_errors() {
+		async fn decode(body: &'static str) -> Vec<Result<StreamEvent>> {
+			let bytes = futures::stream::iter([Ok(bytes::Bytes::from_static(body.as_bytes()))]);
+			sse_events(bytes.boxed()).collect().await
+		}
+		let stopped = decode(concat!(
+			"event: message_delta\n",
+			"data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"stop_sequence\",\"stop_sequence\":\"STOP\"}}\n\n"
+		))
+		.await;
+		assert!(matches!(&stopped[..], [Ok(StreamEvent::Stop { reason: Some(r), stop_sequence: Some(s) })] if r == "stop_sequence" && s == "STOP"));
+
+		let overloaded = decode(concat!(
+			"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"a, b\"}}\n\n",
+			"event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n"
+		))
+		.await;
+		assert!(overloaded.iter().any(|e| matches!(e, Ok(StreamEvent::Text(t)) if t == "a, b")));
+		assert!(overloaded.iter().any(|e| matches!(e, Err(Error::Overloaded))));
+
+		let refused = decode("event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"refusal\"}}\n\n").await;
+		assert!(matches!(&refused[..], [Err(Error::Refusal(_))]));
+	}
+
+	#[test]
+	fn strict_schema_closes_objects() {
+		#[allow(dead_code)]
+		#[derive(schemars::JsonSchema)]
+		struct Primes {
+			primes: Vec<u32>,
+			minimum: Option<String>,
+		}
+		let schema = super::strict_schema(&schemars::schema_for!(Primes).to_value());
+		assert_eq!(schema["additionalProperties"], false);
+		assert!(schema.get("$schema").is_none());
+		assert_eq!(schema["properties"]["primes"]["items"], serde_json::json!({"type": "integer"}));
+		assert!(schema["properties"].get("minimum").is_some());
+	}
+
+	#[test]
+	fn cost_from_usage() {
+		let usage = crate::Usage {
+			input: 1_000_000,
+			output: 100_000,
+			..Default::default()
+		};
+		assert_eq!(super::ClaudeModel::Sonnet45.cost_cents(&usage), 300.0 + 150.0);
+
+		let cached = crate::Usage {
+			input: 1_000,
+			cache_read: 1_000_000,
+			cache_write: 100_000,
+			..Default::default()
+		};
+		assert_eq!(super::ClaudeModel::Sonnet45.cost_cents(&cached), 0.3 + 30.0 + 37.5);
+	}
+
 	#[test]
 	fn deser_model() {
 		let model = "claude-haiku-4-5-20251001".parse::<super::ClaudeModel>().unwrap();
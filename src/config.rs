@@ -1,9 +1,165 @@
 use v_utils::macros::MyConfigPrimitives;
 #[cfg(feature = "cli")]
-use v_utils::macros::Settings;
+use v_utils::macros::{Settings, SettingsNested};
+
+use crate::{Error, Model};
 
 #[derive(Clone, Debug, Default, MyConfigPrimitives)]
 #[cfg_attr(feature = "cli", derive(Settings))]
 pub struct AppConfig {
 	pub claude_token: Option<String>,
+	pub gemini_token: Option<String>,
+	/// Used by tiers mapped to the `openai` provider
+	pub openai_token: Option<String>,
+	#[serde(default)]
+	#[cfg_attr(feature = "cli", settings(flatten))]
+	pub models: Models,
+}
+
+/// Per-tier overrides of where a [Model] is served from. Unset tiers keep the built-in mapping.
+///
+/// ```toml
+/// [models]
+/// medium = "claude:claude-sonnet-4-5"
+/// cheap = "ollama:qwen3.5:4b@http://gpu-box:11434"
+/// ```
+#[derive(Clone, Debug, Default, MyConfigPrimitives)]
+#[cfg_attr(feature = "cli", derive(SettingsNested))]
+pub struct Models {
+	#[private_value]
+	pub cheap: Option<ModelSpec>,
+	#[private_value]
+	pub translate: Option<ModelSpec>,
+	#[private_value]
+	pub fast: Option<ModelSpec>,
+	#[private_value]
+	pub medium: Option<ModelSpec>,
+	#[private_value]
+	pub slow: Option<ModelSpec>,
+}
+impl Models {
+	pub fn get(&self, model: Model) -> Option<&ModelSpec> {
+		match model {
+			Model::Cheap => self.cheap.as_ref(),
+			Model::Translate => self.translate.as_ref(),
+			Model::Fast => self.fast.as_ref(),
+			Model::Medium => self.medium.as_ref(),
+			Model::Slow => self.slow.as_ref(),
+		}
+	}
+}
+
+/// Provider, model id and optionally endpoint, written `provider:model[@endpoint]`.
+///
+/// Endpoint is the base url the provider's API path is appended to, defaulting to
+/// `https://api.anthropic.com` (`claude`), `http://localhost:11434` (`ollama`), `https://api.openai.com/v1` (`openai`)
+/// and `https://generativelanguage.googleapis.com/v1beta` (`gemini`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelSpec {
+	pub provider: Provider,
+	pub model: String,
+	pub endpoint: Option<String>,
+}
+impl ModelSpec {
+	pub fn new(provider: Provider, model: impl Into<String>) -> Self {
+		Self {
+			provider,
+			model: model.into(),
+			endpoint: None,
+		}
+	}
+
+	pub fn endpoint_or_default(&self) -> &str {
+		self.endpoint.as_deref().unwrap_or(match self.provider {
+			Provider::Claude => "https://api.anthropic.com",
+			Provider::Ollama => "http://localhost:11434",
+			Provider::OpenAi => "https://api.openai.com/v1",
+			Provider::Gemini => "https://generativelanguage.googleapis.com/v1beta",
+		})
+	}
+}
+impl std::str::FromStr for ModelSpec {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (provider, rest) = s.split_once(':').ok_or_else(|| Error::Config(format!("Expected `provider:model[@endpoint]`, got `{s}`")))?;
+		let provider: Provider = provider.parse()?;
+		let (model, endpoint) = match rest.split_once('@') {
+			Some((model, endpoint)) => (model, Some(endpoint.trim_end_matches('/').to_string())),
+			None => (rest, None),
+		};
+		if model.is_empty() {
+			return Err(Error::Config(format!("No model id in `{s}`")));
+		}
+		// pricing is looked up by family, so refuse ids we can't price
+		if provider == Provider::Claude {
+			model.parse::<crate::claude::ClaudeModel>()?;
+		}
+		Ok(Self {
+			provider,
+			model: model.to_string(),
+			endpoint,
+		})
+	}
+}
+impl std::fmt::Display for ModelSpec {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.provider, self.model)?;
+		if let Some(endpoint) = &self.endpoint {
+			write!(f, "@{endpoint}")?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+	Claude,
+	Ollama,
+	/// Any OpenAI Chat Completions compatible server
+	OpenAi,
+	Gemini,
+}
+impl std::str::FromStr for Provider {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s.to_lowercase().as_str() {
+			"claude" | "anthropic" => Self::Claude,
+			"ollama" => Self::Ollama,
+			"openai" => Self::OpenAi,
+			"gemini" | "google" => Self::Gemini,
+			_ => return Err(Error::Config(format!("Unknown provider: {s}"))),
+		})
+	}
+}
+impl std::fmt::Display for Provider {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let s = match self {
+			Self::Claude => "claude",
+			Self::Ollama => "ollama",
+			Self::OpenAi => "openai",
+			Self::Gemini => "gemini",
+		};
+		write!(f, "{s}")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_model_spec() {
+		let spec: ModelSpec = "ollama:qwen3.5:4b@http://gpu-box:11434/".parse().unwrap();
+		assert_eq!(spec.provider, Provider::Ollama);
+		assert_eq!(spec.model, "qwen3.5:4b");
+		assert_eq!(spec.endpoint_or_default(), "http://gpu-box:11434");
+		assert_eq!(spec.to_string(), "ollama:qwen3.5:4b@http://gpu-box:11434");
+
+		let spec: ModelSpec = "claude:claude-sonnet-4-5".parse().unwrap();
+		assert_eq!(spec.endpoint_or_default(), "https://api.anthropic.com");
+		assert!("claude:gpt-4o".parse::<ModelSpec>().is_err());
+		assert!("qwen3.5".parse::<ModelSpec>().is_err());
+	}
 }
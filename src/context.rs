@@ -0,0 +1,364 @@
+use std::borrow::Cow;
+
+use crate::{Client, ContentPart, Conversation, Error, Message, MessageContent, Model, Request, Response, Result, Role, ThinkingLevel, Usage};
+
+/// How a [Client] keeps conversations within the model's context window, see [Client::context_strategy].
+///
+/// A turn is a user message and everything up to the next one, so tool calls are never separated from their results.
+/// The window is only known for Claude; elsewhere only [KeepLast](Self::KeepLast) has an effect.
+/// The caller's [Conversation] is never modified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContextStrategy {
+	/// Send as is. A conversation that doesn't fit fails with [Error::ContextTooLong] before sending.
+	#[default]
+	None,
+	/// Once it doesn't fit, drop the oldest turns until it does
+	DropOldest,
+	/// Always send just the system prompt and the last `n` turns, at least one
+	KeepLast(usize),
+	/// Once it doesn't fit, have [Model::Cheap] summarize all but the last `keep_last` turns, at least one, into the system prompt.
+	/// The summary isn't kept, so from then on every request pays for a summary call too, see [Response::cost_cents].
+	Summarize { keep_last: usize },
+}
+
+/// A conversation as it should be sent, and what fitting it cost.
+pub(crate) struct Fitted<'a> {
+	pub conv: Cow<'a, Conversation>,
+	pub cost: FitCost,
+}
+
+/// Of the summary request, if there was one; it's already in the client's spending and ledger.
+#[derive(Default)]
+pub(crate) struct FitCost {
+	usage: Usage,
+	cost_cents: f32,
+}
+impl FitCost {
+	/// Add to `response`, the reply to the fitted conversation.
+	pub fn charge(&self, response: &mut Response) {
+		response.usage += self.usage;
+		response.cost_cents += self.cost_cents;
+	}
+}
+
+/// `conv` as it should be sent, per the client's [ContextStrategy].
+pub(crate) async fn fit<'a>(client: &Client, conv: &'a Conversation) -> Result<Fitted<'a>> {
+	let fitted = |conv| Fitted { conv, cost: FitCost::default() };
+	let mut conv = Cow::Borrowed(conv);
+	if let ContextStrategy::KeepLast(n) = client.context_strategy {
+		// the last turn holds the message being answered
+		let n = n.max(1);
+		let turns = turn_starts(&conv).len();
+		if turns > n {
+			conv = Cow::Owned(drop_turns(&conv, turns - n));
+		}
+	}
+
+	let request = client.request(&conv);
+	let Some(limit) = client.backend.input_limit(&request) else {
+		return Ok(fitted(conv));
+	};
+	// the estimate is rough, so it's only trusted well below the limit. The exact count costs a round trip.
+	let estimate = request.estimate_tokens();
+	if estimate < limit / 4 * 3 {
+		return Ok(fitted(conv));
+	}
+	let mut tokens = client.backend.count_tokens(&request).await?;
+	if tokens <= limit {
+		return Ok(fitted(conv));
+	}
+	let overflow = |tokens: u32| Error::ContextTooLong(format!("{tokens} input tokens, but only {limit} fit alongside the reply"));
+
+	let mut conv = conv.into_owned();
+	let mut cost = FitCost::default();
+	match client.context_strategy {
+		ContextStrategy::None | ContextStrategy::KeepLast(_) => return Err(overflow(tokens)),
+		ContextStrategy::DropOldest => {
+			// tokens per estimated token, to drop about enough turns before counting again
+			let scale = tokens as f32 / estimate.max(1) as f32;
+			while tokens > limit {
+				let starts = turn_starts(&conv);
+				let (mut n, mut dropped) = (0, 0.0);
+				while tokens as f32 - dropped > limit as f32 && n + 1 < starts.len() {
+					dropped += estimate_messages(&conv.0[starts[n]..starts[n + 1]]) as f32 * scale;
+					n += 1;
+				}
+				if n == 0 {
+					return Err(overflow(tokens));
+				}
+				conv = drop_turns(&conv, n);
+				tokens = client.backend.count_tokens(&client.request(&conv)).await?;
+			}
+		}
+		ContextStrategy::Summarize { keep_last } => {
+			let keep_last = keep_last.max(1);
+			let turns = turn_starts(&conv).len();
+			if turns <= keep_last {
+				return Err(overflow(tokens));
+			}
+			let summary;
+			(conv, summary) = summarize(client, &conv, turns - keep_last).await?;
+			cost = FitCost {
+				usage: summary.usage,
+				cost_cents: summary.cost_cents,
+			};
+			tokens = client.backend.count_tokens(&client.request(&conv)).await?;
+			if tokens > limit {
+				return Err(overflow(tokens));
+			}
+		}
+	}
+	tracing::info!("Fit conversation into the context window: {} messages, {tokens} tokens", conv.0.len());
+	Ok(Fitted { conv: Cow::Owned(conv), cost })
+}
+
+/// Index of the first message of each turn. Anything between the system prompt and the first user message counts towards the first turn.
+fn turn_starts(conv: &Conversation) -> Vec<usize> {
+	let Some(first) = conv.0.iter().position(|m| !matches!(m.role, Role::System)) else {
+		return Vec::new();
+	};
+	std::iter::once(first)
+		.chain((first + 1..conv.0.len()).filter(|&i| matches!(conv.0[i].role, Role::User) && !matches!(conv.0[i].content, MessageContent::ToolResults(_))))
+		.collect()
+}
+
+/// `conv` without its first `n` turns.
+fn drop_turns(conv: &Conversation, n: usize) -> Conversation {
+	let starts = turn_starts(conv);
+	let mut conv = conv.clone();
+	if let (Some(&start), Some(&end)) = (starts.first(), starts.get(n).or(Some(&conv.0.len()))) {
+		conv.0.drain(start..end);
+	}
+	conv
+}
+
+fn estimate_messages(messages: &[Message]) -> u32 {
+	messages.iter().map(|m| serde_json::to_string(m).map_or(0, |s| s.len() / 4) as u32).sum()
+}
+
+/// `conv` with its first `n` turns replaced by a summary at the end of the system prompt, and the reply that summarized them.
+/// The summary request goes through `client`, so it counts towards its budget, ledger and cache.
+async fn summarize(client: &Client, conv: &Conversation, n: usize) -> Result<(Conversation, Response)> {
+	const PROMPT: &str = "Summarize the conversation below for the assistant that will continue it. \
+		Keep every fact, decision, open question and user preference that could matter later; drop pleasantries. Reply with the summary only.";
+
+	let starts = turn_starts(conv);
+	let end = starts.get(n).copied().unwrap_or(conv.0.len());
+	let transcript = conv.0[starts[0]..end].iter().map(transcript_entry).collect::<Vec<_>>().join("\n\n");
+	let mut request = Conversation::new_with_system(PROMPT);
+	request.add(Role::User, transcript);
+	let request = Request {
+		conversation: &request,
+		temperature: None,
+		max_tokens: None,
+		stop_sequences: None,
+		force_json: false,
+		json_schema: None,
+		files: &[],
+		thinking: ThinkingLevel::None,
+		tools: &[],
+	};
+	let summarizer = Model::Cheap.into_backend(&client.config);
+	// not `conversation`, which would fit the request again
+	let response = client.send_via(summarizer.as_ref(), &request).await?;
+
+	let mut conv = drop_turns(conv, n);
+	let summary = format!("Summary of the earlier conversation:\n{}", response.text.trim());
+	match conv.0.first_mut() {
+		Some(Message {
+			role: Role::System,
+			content: MessageContent::Text(system),
+			..
+		}) => {
+			system.push_str("\n\n");
+			system.push_str(&summary);
+		}
+		_ => conv.0.insert(0, Message::new(Role::System, summary)),
+	}
+	Ok((conv, response))
+}
+
+fn transcript_entry(message: &Message) -> String {
+	let role = match message.role {
+		Role::System => "System",
+		Role::User => "User",
+		Role::Assistant => "Assistant",
+	};
+	let content = match &message.content {
+		MessageContent::Text(text) => text.clone(),
+		MessageContent::Image { .. } => "[image]".to_string(),
+		MessageContent::TextAndImages { text, images } => format!("{text} [{} images]", images.len()),
+		MessageContent::Document { media_type, .. } => format!("[{media_type} document]"),
+		MessageContent::Mixed { parts } => parts
+			.iter()
+			.map(|part| match part {
+				ContentPart::Text(text) => text.clone(),
+				ContentPart::Image { .. } => "[image]".to_string(),
+				ContentPart::Document { media_type, .. } => format!("[{media_type} document]"),
+			})
+			.collect::<Vec<_>>()
+			.join(" "),
+		MessageContent::ToolUse { text, calls } => {
+			let calls = calls.iter().map(|c| format!("[called {}({})]", c.name, c.input)).collect::<Vec<_>>().join(" ");
+			format!("{text} {calls}")
+		}
+		MessageContent::ToolResults(results) => results.iter().map(|r| format!("[tool result: {}]", r.content)).collect::<Vec<_>>().join(" "),
+	};
+	format!("{role}: {}", content.trim())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{future::Future, pin::Pin};
+
+	use super::*;
+	use crate::{
+		Backend, Ledger, ResponseStream, ToolCall, ToolResult,
+		config::{AppConfig, ModelSpec, Provider},
+		testing::{MockBackend, fake_server, temp_path},
+	};
+
+	/// [MockBackend] with a known input limit, so that [fit] has something to fit into
+	struct Windowed(MockBackend, u32);
+	impl Backend for Windowed {
+		fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>> {
+			self.0.conversation(request)
+		}
+
+		fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>> {
+			self.0.stream(request)
+		}
+
+		fn name(&self) -> String {
+			self.0.name()
+		}
+
+		fn input_limit(&self, _request: &Request<'_>) -> Option<u32> {
+			Some(self.1)
+		}
+	}
+
+	/// System prompt and `turns` exchanges of about 500 tokens each
+	fn long_conversation(turns: usize) -> Conversation {
+		let mut conv = Conversation::new_with_system("be brief");
+		for i in 0..turns {
+			conv.add_exchange(format!("question {i}: {}", "x".repeat(1000)), format!("answer {i}: {}", "y".repeat(1000)));
+		}
+		conv
+	}
+
+	#[tokio::test]
+	async fn summary_is_charged() {
+		let (url, server) = fake_server(
+			"application/json",
+			r#"{"message":{"role":"assistant","content":"They asked two questions."},"done":true,"done_reason":"stop","prompt_eval_count":50,"eval_count":10}"#,
+		)
+		.await;
+		let mut config = AppConfig::default();
+		config.models.cheap = Some(ModelSpec {
+			endpoint: Some(url),
+			..ModelSpec::new(Provider::Ollama, "summarizer")
+		});
+		let ledger = Ledger {
+			path: temp_path("summary_ledger.jsonl"),
+		};
+		let mock = MockBackend::new().respond(Response {
+			text: "third answer".to_string(),
+			cost_cents: 1.0,
+			usage: Usage { input: 7, ..Default::default() },
+			..Default::default()
+		});
+		let requests = mock.requests();
+		let client = Client::with_backend(config, Windowed(mock, 1000))
+			.context_strategy(ContextStrategy::Summarize { keep_last: 1 })
+			.ledger(ledger.clone());
+
+		let mut conv = long_conversation(2);
+		conv.add(Role::User, "third question");
+		let response = client.conversation(&conv).await.unwrap();
+		assert_eq!(response.text, "third answer");
+		assert_eq!((response.usage.input, response.usage.output, response.cost_cents), (57, 10, 1.0));
+
+		let sent = requests.last().unwrap().conversation;
+		assert_eq!(sent.0.len(), 2);
+		assert!(matches!(&sent.0[0].content, MessageContent::Text(t) if t.ends_with("They asked two questions.")));
+		assert!(server.await.unwrap().contains("question 1"));
+		assert_eq!(ledger.entries().unwrap().len(), 2, "the summary request is in the ledger too");
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+
+	#[tokio::test]
+	async fn keep_last_and_drop_oldest() {
+		let mock = MockBackend::new().reply("a").reply("b");
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), mock).context_strategy(ContextStrategy::KeepLast(1));
+		let mut conv = long_conversation(3);
+		conv.add(Role::User, "fourth question");
+		client.conversation(&conv).await.unwrap();
+		let sent = requests.last().unwrap().conversation;
+		assert_eq!(sent.0.len(), 2);
+		assert!(matches!(&sent.0[1].content, MessageContent::Text(t) if t == "fourth question"));
+		assert_eq!(conv.0.len(), 8, "the caller's conversation is left alone");
+
+		let mock = MockBackend::new().reply("a");
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), mock).context_strategy(ContextStrategy::KeepLast(0));
+		client.conversation(&conv).await.unwrap();
+		assert_eq!(requests.last().unwrap().conversation.0.len(), 2, "the turn being answered is always kept");
+
+		let mock = MockBackend::new().reply("a");
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), Windowed(mock, 1200)).context_strategy(ContextStrategy::DropOldest);
+		client.conversation(&conv).await.unwrap();
+		let sent = requests.last().unwrap().conversation;
+		assert!(sent.0.len() < conv.0.len() && sent.0.len() >= 2);
+		assert!(matches!(&sent.0[0].role, Role::System));
+		assert!(client.request(&sent).estimate_tokens() <= 1200);
+	}
+
+	#[tokio::test]
+	async fn too_long_fails_before_sending() {
+		let mock = MockBackend::new().reply("never sent");
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), Windowed(mock, 1000));
+		assert!(matches!(client.conversation(&long_conversation(4)).await, Err(Error::ContextTooLong(_))));
+		assert!(requests.is_empty());
+
+		// keeping the last turns doesn't help when those alone are too long
+		let client = Client::with_backend(AppConfig::default(), Windowed(MockBackend::new(), 400)).context_strategy(ContextStrategy::KeepLast(1));
+		assert!(matches!(client.conversation(&long_conversation(4)).await, Err(Error::ContextTooLong(_))));
+	}
+
+	#[test]
+	fn drops_whole_turns() {
+		let mut conv = Conversation::new_with_system("be brief");
+		conv.add(Role::User, "weather?");
+		conv.0.push(Message {
+			role: Role::Assistant,
+			content: MessageContent::ToolUse {
+				text: String::new(),
+				calls: vec![ToolCall {
+					id: "1".to_string(),
+					name: "weather".to_string(),
+					input: serde_json::json!({}),
+				}],
+			},
+			cache: false,
+			thinking: Vec::new(),
+		});
+		conv.0.push(Message::new_tool_results(vec![ToolResult {
+			tool_use_id: "1".to_string(),
+			content: "sunny".to_string(),
+			is_error: false,
+		}]));
+		conv.add(Role::Assistant, "sunny");
+		conv.add(Role::User, "thanks");
+
+		assert_eq!(turn_starts(&conv), [1, 5]);
+		let trimmed = drop_turns(&conv, 1);
+		assert_eq!(trimmed.0.len(), 2);
+		assert!(matches!(trimmed.0[0].role, Role::System));
+		assert!(matches!(&trimmed.0[1].content, MessageContent::Text(t) if t == "thanks"));
+		assert_eq!(drop_turns(&conv, 2).0.len(), 1);
+	}
+}
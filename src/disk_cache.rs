@@ -0,0 +1,233 @@
+use std::{
+	path::PathBuf,
+	time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Request, Response, Result, ThinkingBlock, ThinkingLevel, ToolCall, Usage};
+
+/// Opt-in on-disk cache of replies, keyed by everything in the request that affects the answer. See [Client::disk_cache](crate::Client::disk_cache).
+///
+/// Only [Client::conversation](crate::Client::conversation) and what's built on it are cached, not streams.
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+	/// Defaults to `ask_llm` under the user's cache dir
+	pub dir: PathBuf,
+	/// Older entries count as misses
+	pub ttl: Duration,
+	/// Once exceeded, least recently written entries are evicted
+	pub max_bytes: u64,
+	/// Ignore stored replies, but still store the fresh ones
+	pub bypass: bool,
+}
+impl Default for DiskCache {
+	fn default() -> Self {
+		Self {
+			dir: dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("ask_llm"),
+			ttl: Duration::from_secs(7 * 24 * 60 * 60),
+			max_bytes: 256 * 1024 * 1024,
+			bypass: false,
+		}
+	}
+}
+
+/// The parts of a [Response] worth storing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Entry {
+	text: String,
+	usage: Usage,
+	model: String,
+	thinking: ThinkingLevel,
+	tool_calls: Vec<ToolCall>,
+	#[serde(default)]
+	thinking_blocks: Vec<ThinkingBlock>,
+	#[serde(default)]
+	stop_reason: Option<String>,
+	#[serde(default)]
+	stop_sequence: Option<String>,
+}
+impl Entry {
+	pub fn new(response: &Response) -> Self {
+		Self {
+			text: response.text.clone(),
+			usage: response.usage,
+			model: response.model.clone(),
+			thinking: response.thinking,
+			tool_calls: response.tool_calls.clone(),
+			thinking_blocks: response.thinking_blocks.clone(),
+			stop_reason: response.stop_reason.clone(),
+			stop_sequence: response.stop_sequence.clone(),
+		}
+	}
+
+	/// Costs nothing and took no time, as it was already paid for.
+	pub fn into_response(self) -> Response {
+		Response {
+			text: self.text,
+			usage: self.usage,
+			model: self.model,
+			thinking: self.thinking,
+			tool_calls: self.tool_calls,
+			thinking_blocks: self.thinking_blocks,
+			stop_reason: self.stop_reason,
+			stop_sequence: self.stop_sequence,
+			..Default::default()
+		}
+	}
+}
+
+impl DiskCache {
+	/// Remove all stored replies.
+	pub fn clear(&self) -> Result<()> {
+		match std::fs::remove_dir_all(&self.dir) {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+			_ => Ok(()),
+		}
+	}
+
+	/// Hash of the [canonical](Self::canonical) JSON of `request` as sent to `backend`.
+	pub(crate) fn key(backend: &str, request: &Request<'_>) -> String {
+		let digest = Sha256::digest(Self::canonical(backend, request).to_string().as_bytes());
+		digest.iter().map(|b| format!("{b:02x}")).collect()
+	}
+
+	/// Everything in `request` that affects the reply.
+	pub(crate) fn canonical(backend: &str, request: &Request<'_>) -> serde_json::Value {
+		let tools: Vec<_> = request
+			.tools
+			.iter()
+			.map(|t| serde_json::json!({"name": t.name, "description": t.description, "input_schema": t.input_schema}))
+			.collect();
+		let files: Vec<_> = request.files.iter().map(|f| serde_json::json!({"media_type": f.media_type, "data": f.base64_data})).collect();
+		serde_json::json!({
+			"backend": backend,
+			"conversation": request.conversation,
+			"files": files,
+			"temperature": request.temperature,
+			"max_tokens": request.max_tokens,
+			"stop_sequences": request.stop_sequences,
+			"force_json": request.force_json,
+			"json_schema": request.json_schema,
+			"thinking": request.thinking,
+			"tools": tools,
+		})
+	}
+
+	/// Stored reply for `key`, if there is a fresh one. Failures to read are logged and count as misses.
+	pub(crate) fn get(&self, key: &str) -> Option<Response> {
+		if self.bypass {
+			return None;
+		}
+		let path = self.dir.join(format!("{key}.json"));
+		let age = std::fs::metadata(&path).ok()?.modified().ok()?.elapsed().unwrap_or_default();
+		if age > self.ttl {
+			let _ = std::fs::remove_file(&path);
+			return None;
+		}
+		let entry: Entry = match std::fs::read(&path).map_err(crate::Error::from).and_then(|bytes| Ok(serde_json::from_slice(&bytes)?)) {
+			Ok(entry) => entry,
+			Err(e) => {
+				tracing::warn!("Ignoring unreadable cache entry {}: {e}", path.display());
+				return None;
+			}
+		};
+		Some(Response {
+			cache_hit: true,
+			..entry.into_response()
+		})
+	}
+
+	/// Store `response` under `key`, then evict down to `max_bytes`. Failures are logged, never returned.
+	pub(crate) fn put(&self, key: &str, response: &Response) {
+		if let Err(e) = self.write(key, &Entry::new(response)).and_then(|()| self.evict()) {
+			tracing::warn!("Failed to update response cache at {}: {e}", self.dir.display());
+		}
+	}
+
+	fn write(&self, key: &str, entry: &Entry) -> Result<()> {
+		std::fs::create_dir_all(&self.dir)?;
+		// write-then-rename, so concurrent readers never see a partial entry
+		let tmp = self.dir.join(format!("{key}.json.tmp"));
+		std::fs::write(&tmp, serde_json::to_vec(entry)?)?;
+		std::fs::rename(&tmp, self.dir.join(format!("{key}.json")))?;
+		Ok(())
+	}
+
+	fn evict(&self) -> Result<()> {
+		let mut entries = Vec::new();
+		for dir_entry in std::fs::read_dir(&self.dir)? {
+			let dir_entry = dir_entry?;
+			let metadata = dir_entry.metadata()?;
+			entries.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len(), dir_entry.path()));
+		}
+		let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+		entries.sort_by_key(|(modified, ..)| *modified);
+		for (_, len, path) in entries {
+			if total <= self.max_bytes {
+				break;
+			}
+			std::fs::remove_file(&path)?;
+			total -= len;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		Conversation, Role,
+		testing::{request, temp_path},
+	};
+
+	#[test]
+	fn store_lookup_and_evict() {
+		let cache = DiskCache {
+			dir: temp_path("cache"),
+			..Default::default()
+		};
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "2+2?");
+		let mut request = request(&conv);
+		let key = DiskCache::key("ollama:x", &request);
+		assert_eq!(key, DiskCache::key("ollama:x", &request));
+		assert_ne!(key, DiskCache::key("ollama:y", &request));
+		request.temperature = Some(0.5);
+		assert_ne!(key, DiskCache::key("ollama:x", &request));
+
+		assert!(cache.get(&key).is_none());
+		let response = Response {
+			text: "4".to_string(),
+			cost_cents: 1.0,
+			usage: Usage::default(),
+			duration: Duration::from_secs(1),
+			overhead: Duration::ZERO,
+			model: "x".to_string(),
+			thinking: ThinkingLevel::None,
+			..
+		};
+		cache.put(&key, &response);
+		let hit = cache.get(&key).unwrap();
+		assert!(hit.cache_hit);
+		assert_eq!(hit.text, "4");
+		assert!(DiskCache { bypass: true, ..cache.clone() }.get(&key).is_none());
+		assert!(
+			DiskCache {
+				ttl: Duration::ZERO,
+				..cache.clone()
+			}
+			.get(&key)
+			.is_none()
+		);
+
+		let tiny = DiskCache { max_bytes: 0, ..cache.clone() };
+		tiny.put(&key, &response);
+		assert!(cache.get(&key).is_none());
+
+		cache.clear().unwrap();
+		assert!(!cache.dir.exists());
+	}
+}
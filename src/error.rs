@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+	/// No API key for the selected provider in config or environment.
+	MissingCredentials {
+		env_var: &'static str,
+	},
+	/// HTTP 429. `retry_after` is what the provider asked us to wait, if it said.
+	RateLimited {
+		retry_after: Option<Duration>,
+	},
+	/// Provider is temporarily out of capacity (Anthropic's 529, 503s elsewhere).
+	Overloaded,
+	/// Request doesn't fit into the model's context window.
+	ContextTooLong(String),
+	/// Model declined to answer.
+	Refusal(String),
+	/// Provider replied with something we couldn't make sense of, or the reply didn't have the requested shape.
+	InvalidResponse(String),
+	/// Any other non-success HTTP status.
+	Api {
+		status: u16,
+		message: String,
+	},
+	/// Failed to reach the provider or read its reply.
+	Transport(reqwest::Error),
+	/// Request contains something the backend can't send, eg an image to a text-only endpoint.
+	UnsupportedContent {
+		backend: &'static str,
+		detail: String,
+	},
+	/// Sending the request could take spending over a [Budget](crate::Budget) cap. `request_cents` is its estimated worst case.
+	BudgetExceeded {
+		budget: &'static str,
+		limit_cents: f32,
+		spent_cents: f32,
+		request_cents: f32,
+	},
+	/// Invalid client setup, eg an unknown model name.
+	Config(String),
+	Io(std::io::Error),
+}
+
+impl Error {
+	/// Classify a non-success HTTP response, reading `retry-after` and the body.
+	pub(crate) async fn from_response(response: reqwest::Response) -> Self {
+		let status = response.status().as_u16();
+		let retry_after = response
+			.headers()
+			.get(reqwest::header::RETRY_AFTER)
+			.and_then(|v| v.to_str().ok())
+			.and_then(|v| v.trim().parse::<f64>().ok())
+			.map(Duration::from_secs_f64)
+			.or_else(|| ratelimit_reset(response.headers()));
+		let message = response.text().await.unwrap_or_default();
+		Self::from_status(status, retry_after, message)
+	}
+
+	/// Whether sending the same request again may succeed: rate limits, overload, 5xx and network failures.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Self::RateLimited { .. } | Self::Overloaded => true,
+			Self::Api { status, .. } => *status >= 500,
+			Self::Transport(e) => !e.is_builder(),
+			_ => false,
+		}
+	}
+
+	pub(crate) fn from_status(status: u16, retry_after: Option<Duration>, message: String) -> Self {
+		match status {
+			429 => Self::RateLimited { retry_after },
+			503 | 529 => Self::Overloaded,
+			413 => Self::ContextTooLong(message),
+			_ if is_context_overflow(&message) => Self::ContextTooLong(message),
+			_ => Self::Api { status, message },
+		}
+	}
+}
+
+/// Anthropic's `anthropic-ratelimit-*-reset` headers are RFC 3339 timestamps; wait for the latest of the exhausted limits.
+fn ratelimit_reset(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+	let now = jiff::Timestamp::now();
+	["requests", "tokens", "input-tokens", "output-tokens"]
+		.iter()
+		.filter(|limit| headers.get(format!("anthropic-ratelimit-{limit}-remaining")).and_then(|v| v.to_str().ok()) == Some("0"))
+		.filter_map(|limit| headers.get(format!("anthropic-ratelimit-{limit}-reset"))?.to_str().ok()?.parse::<jiff::Timestamp>().ok())
+		.map(|reset| Duration::try_from(reset.duration_since(now)).unwrap_or_default())
+		.max()
+}
+
+/// Providers report context overflow as a plain 400; these are the phrasings used by Anthropic, OpenAI, Gemini and Ollama/llama.cpp.
+fn is_context_overflow(message: &str) -> bool {
+	let message = message.to_lowercase();
+	[
+		"prompt is too long",
+		"context_length_exceeded",
+		"maximum context length",
+		"exceeds the context",
+		"input token count",
+	]
+	.iter()
+	.any(|p| message.contains(p))
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::MissingCredentials { env_var } => write!(f, "{env_var} not set in config or environment"),
+			Self::RateLimited { retry_after: Some(d) } => write!(f, "Rate limited, retry after {:.1}s", d.as_secs_f32()),
+			Self::RateLimited { retry_after: None } => write!(f, "Rate limited"),
+			Self::Overloaded => write!(f, "Provider is overloaded"),
+			Self::ContextTooLong(message) => write!(f, "Request exceeds the context window: {message}"),
+			Self::Refusal(reason) => write!(f, "Model refused to process the request: {reason}"),
+			Self::InvalidResponse(message) => write!(f, "Invalid response: {message}"),
+			Self::Api { status, message } => write!(f, "Request failed ({status}): {message}"),
+			Self::Transport(e) => write!(f, "Transport error: {e}"),
+			Self::UnsupportedContent { backend, detail } => write!(f, "{backend} backend does not support {detail}"),
+			Self::BudgetExceeded {
+				budget,
+				limit_cents,
+				spent_cents,
+				request_cents,
+			} => write!(
+				f,
+				"Refusing request: {spent_cents:.4}¢ spent + up to {request_cents:.4}¢ would exceed the {budget} budget of {limit_cents:.4}¢"
+			),
+			Self::Config(message) => write!(f, "Invalid configuration: {message}"),
+			Self::Io(e) => write!(f, "{e}"),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Transport(e) => Some(e),
+			Self::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<reqwest::Error> for Error {
+	fn from(e: reqwest::Error) -> Self {
+		Self::Transport(e)
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(e: serde_json::Error) -> Self {
+		Self::InvalidResponse(e.to_string())
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(e: std::io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn classify_status() {
+		assert!(matches!(Error::from_status(429, Some(Duration::from_secs(3)), String::new()), Error::RateLimited { retry_after: Some(d) } if d.as_secs() == 3));
+		assert!(matches!(Error::from_status(529, None, String::new()), Error::Overloaded));
+		assert!(matches!(
+			Error::from_status(400, None, r#"{"error":{"message":"prompt is too long: 250000 tokens > 200000 maximum"}}"#.to_string()),
+			Error::ContextTooLong(_)
+		));
+		assert!(matches!(Error::from_status(401, None, "bad key".to_string()), Error::Api { status: 401, .. }));
+	}
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use crate::{Backend, Error, Request, Response, ResponseStream, Result, StreamEvent};
+use futures::StreamExt;
+
+/// Tries each backend in order, moving on when one is unavailable or can't handle the request. See [Client::fallback](crate::Client::fallback).
+pub(crate) struct Fallback {
+	pub backends: Vec<Box<dyn Backend>>,
+}
+impl Fallback {
+	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
+		let mut skipped = Vec::new();
+		for (i, backend) in self.backends.iter().enumerate() {
+			match backend.conversation(request).await {
+				Ok(mut response) => {
+					if response.backend.is_empty() {
+						response.backend = backend.name();
+					}
+					skipped.append(&mut response.skipped);
+					response.skipped = skipped;
+					return Ok(response);
+				}
+				Err(e) if i + 1 < self.backends.len() && falls_through(&e) => {
+					tracing::warn!("{} failed, falling back: {e}", backend.name());
+					skipped.push(Skipped {
+						backend: backend.name(),
+						error: Arc::new(e),
+					});
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		Err(Error::Config("Empty fallback chain".to_string()))
+	}
+
+	/// Falls through only while opening the stream; failures after that are the consumer's to handle.
+	/// The stream opens with a [StreamEvent::Backend] naming the backend that answers and those passed over.
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		let mut skipped = Vec::new();
+		for (i, backend) in self.backends.iter().enumerate() {
+			match backend.stream(request).await {
+				Ok(stream) => {
+					let answering = StreamEvent::Backend { name: backend.name(), skipped };
+					return Ok(Box::pin(futures::stream::once(std::future::ready(Ok(answering))).chain(stream)));
+				}
+				Err(e) if i + 1 < self.backends.len() && falls_through(&e) => {
+					tracing::warn!("{} failed, falling back: {e}", backend.name());
+					skipped.push(Skipped {
+						backend: backend.name(),
+						error: Arc::new(e),
+					});
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		Err(Error::Config("Empty fallback chain".to_string()))
+	}
+}
+
+/// Backend of a fallback chain that was passed over, see [Response::skipped].
+#[derive(Clone, Debug)]
+pub struct Skipped {
+	/// `provider:model`
+	pub backend: String,
+	/// Shared, so that it can be part of a [StreamEvent::Backend]
+	pub error: Arc<Error>,
+}
+
+/// Whether another backend could plausibly succeed where this one failed.
+fn falls_through(e: &Error) -> bool {
+	e.is_retryable() || matches!(e, Error::UnsupportedContent { .. } | Error::MissingCredentials { .. })
+}
+
+impl Backend for Fallback {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(self.do_conversation(request))
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		self.backends.iter().map(|b| b.name()).collect::<Vec<_>>().join(" -> ")
+	}
+
+	/// Not knowing which backend answered, as the first one.
+	fn cost_cents(&self, usage: &crate::Usage) -> f32 {
+		self.backends.first().map_or(0.0, |b| b.cost_cents(usage))
+	}
+
+	/// As the backend that answered, which may be further down a nested chain.
+	fn stream_cost_cents(&self, backend: &str, usage: &crate::Usage) -> f32 {
+		let answered = self.backends.iter().find(|b| b.name().split(" -> ").any(|name| name == backend));
+		answered.map_or_else(|| self.cost_cents(usage), |b| b.stream_cost_cents(backend, usage))
+	}
+
+	/// As counted by the first backend, the one that normally answers.
+	fn count_tokens<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<u32>> + Send + 'a>> {
+		match self.backends.first() {
+			Some(backend) => backend.count_tokens(request),
+			None => Box::pin(async move { Ok(request.estimate_tokens()) }),
+		}
+	}
+
+	fn max_output(&self, request: &Request<'_>) -> Option<u32> {
+		self.backends.first()?.max_output(request)
+	}
+
+	fn input_limit(&self, request: &Request<'_>) -> Option<u32> {
+		self.backends.first()?.input_limit(request)
+	}
+
+	/// Only if every backend has it, as any of them may answer.
+	fn native_prefill(&self, request: &Request<'_>) -> bool {
+		self.backends.iter().all(|b| b.native_prefill(request))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		Conversation, StreamEvent, ThinkingLevel, Usage,
+		testing::{request, temp_path},
+	};
+
+	struct Stub {
+		name: &'static str,
+		fails_with: Option<fn() -> Error> = None,
+		cents_per_token: f32 = 0.0,
+		input_limit: Option<u32> = None,
+	}
+	impl Backend for Stub {
+		fn conversation<'a>(&'a self, _: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
+			Box::pin(async move {
+				if let Some(error) = self.fails_with {
+					return Err(error());
+				}
+				Ok(Response {
+					text: self.name.to_string(),
+					cost_cents: 0.0,
+					usage: Usage::default(),
+					duration: std::time::Duration::ZERO,
+					overhead: std::time::Duration::ZERO,
+					model: self.name.to_string(),
+					thinking: ThinkingLevel::None,
+					..
+				})
+			})
+		}
+
+		fn stream<'a>(&'a self, _: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+			Box::pin(async move {
+				if let Some(error) = self.fails_with {
+					return Err(error());
+				}
+				let events = [
+					StreamEvent::Text(self.name.to_string()),
+					StreamEvent::Usage(Usage { output: 10, ..Default::default() }),
+					StreamEvent::Stop { reason: None, stop_sequence: None },
+				];
+				Ok(Box::pin(futures::stream::iter(events.map(Ok))) as ResponseStream)
+			})
+		}
+
+		fn name(&self) -> String {
+			self.name.to_string()
+		}
+
+		fn cost_cents(&self, usage: &Usage) -> f32 {
+			usage.output as f32 * self.cents_per_token
+		}
+
+		fn input_limit(&self, _: &Request<'_>) -> Option<u32> {
+			self.input_limit
+		}
+	}
+
+	#[tokio::test]
+	async fn falls_through_unavailable_backends() {
+		let fallback = Fallback {
+			backends: vec![
+				Box::new(Stub {
+					name: "down",
+					fails_with: Some(|| Error::Overloaded),
+					..
+				}),
+				Box::new(Stub {
+					name: "no_key",
+					fails_with: Some(|| Error::MissingCredentials { env_var: "CLAUDE_TOKEN" }),
+					..
+				}),
+				Box::new(Stub { name: "up", .. }),
+			],
+		};
+		let conv = Conversation::new();
+		let request = request(&conv);
+		let response = fallback.conversation(&request).await.unwrap();
+		assert_eq!(response.backend, "up");
+		assert_eq!(response.skipped.iter().map(|s| s.backend.as_str()).collect::<Vec<_>>(), ["down", "no_key"]);
+
+		let fallback = Fallback {
+			backends: vec![
+				Box::new(Stub {
+					name: "refuses",
+					fails_with: Some(|| Error::Refusal(String::new())),
+					..
+				}),
+				Box::new(Stub { name: "up", .. }),
+			],
+		};
+		assert!(matches!(fallback.conversation(&request).await, Err(Error::Refusal(_))));
+	}
+
+	#[tokio::test]
+	async fn stream_falls_through() {
+		let fallback = Fallback {
+			backends: vec![
+				Box::new(Stub {
+					name: "down",
+					fails_with: Some(|| Error::Overloaded),
+					cents_per_token: 1.0,
+					input_limit: Some(1000),
+				}),
+				Box::new(Stub {
+					name: "no_key",
+					fails_with: Some(|| Error::MissingCredentials { env_var: "CLAUDE_TOKEN" }),
+					..
+				}),
+				Box::new(Stub {
+					name: "up",
+					cents_per_token: 2.0,
+					input_limit: Some(2000),
+					..
+				}),
+			],
+		};
+		let conv = Conversation::new();
+		let request = request(&conv);
+		let events = fallback.stream(&request).await.unwrap().collect::<Vec<_>>().await;
+		assert!(events.iter().any(|e| matches!(e, Ok(StreamEvent::Text(t)) if t == "up")));
+
+		assert!(matches!(&events[0], Ok(StreamEvent::Backend { name, skipped }) if name == "up" && skipped.len() == 2));
+
+		// priced as the backend that answered, limited as the first one
+		let usage = Usage { output: 10, ..Default::default() };
+		assert_eq!(fallback.stream_cost_cents("up", &usage), 20.0);
+		assert_eq!(fallback.stream_cost_cents("down -> no_key -> up", &usage), 10.0);
+		assert_eq!(fallback.input_limit(&request), Some(1000));
+
+		let fallback = Fallback {
+			backends: vec![
+				Box::new(Stub {
+					name: "refuses",
+					fails_with: Some(|| Error::Refusal(String::new())),
+					..
+				}),
+				Box::new(Stub { name: "up", .. }),
+			],
+		};
+		assert!(matches!(fallback.stream(&request).await, Err(Error::Refusal(_))));
+	}
+
+	#[tokio::test]
+	async fn streamed_response_names_the_backend_that_answered() {
+		let ledger = crate::Ledger {
+			path: temp_path("fallback_ledger.jsonl"),
+		};
+		let fallback = Fallback {
+			backends: vec![
+				Box::new(Stub {
+					name: "claude:down",
+					fails_with: Some(|| Error::Overloaded),
+					cents_per_token: 1.0,
+					..
+				}),
+				Box::new(Stub {
+					name: "ollama:up",
+					cents_per_token: 0.5,
+					..
+				}),
+			],
+		};
+		let client = crate::Client::with_backend(crate::config::AppConfig::default(), fallback).ledger(ledger.clone());
+		let response = client.conversation_streamed(&Conversation::new(), |_| {}).await.unwrap();
+		assert_eq!((response.backend.as_str(), response.model.as_str()), ("ollama:up", "up"));
+		assert_eq!(response.skipped.iter().map(|s| s.backend.as_str()).collect::<Vec<_>>(), ["claude:down"]);
+		assert!(matches!(*response.skipped[0].error, Error::Overloaded));
+		assert_eq!(response.cost_cents, 5.0);
+		let entry = &ledger.entries().unwrap()[0];
+		assert_eq!((entry.model.as_str(), entry.cost_cents), ("ollama:up", 5.0));
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+}
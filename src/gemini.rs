@@ -0,0 +1,470 @@
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{Backend, ContentPart, Error, FileAttachment, MessageContent, Request, Response, ResponseStream, Result, Role, StreamEvent, ThinkingBlock, ThinkingLevel, Usage};
+
+pub(crate) struct Gemini {
+	pub api_key: Option<String>,
+	pub model: String,
+	/// `https://generativelanguage.googleapis.com/v1beta` unless pointed elsewhere
+	pub base_url: String,
+}
+impl Gemini {
+	///docs: https://ai.google.dev/api/generate-content
+	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
+		let ttfb_start = std::time::Instant::now();
+		let response = self.send(request, false).await?;
+		let ttfb = ttfb_start.elapsed();
+
+		let value: Value = response.json().await?;
+		tracing::debug!(?value);
+		let parsed: GenerateContentResponse = serde_json::from_value(value.clone()).inspect_err(|e| tracing::error!(%value, "Failed to parse Gemini response: {e}"))?;
+
+		if let Some(reason) = parsed.prompt_feedback.and_then(|f| f.block_reason) {
+			return Err(Error::Refusal(reason));
+		}
+		let Some(candidate) = parsed.candidates.into_iter().next() else {
+			return Err(Error::InvalidResponse("Gemini response contained no candidates".to_string()));
+		};
+		if let Some(reason) = candidate.finish_reason.as_deref().filter(|r| is_blocked(r)) {
+			return Err(Error::Refusal(reason.to_string()));
+		}
+
+		let usage = parsed.usage_metadata.map(Usage::from).unwrap_or_default();
+		let (thoughts, parts): (Vec<_>, Vec<_>) = candidate.content.map(|c| c.parts).unwrap_or_default().into_iter().partition(|p| p.thought);
+		let text = parts.into_iter().filter_map(|p| p.text).collect();
+		let thinking: String = thoughts.into_iter().filter_map(|p| p.text).collect();
+		Ok(Response {
+			text,
+			cost_cents: 0.0,
+			usage,
+			duration: std::time::Duration::ZERO,
+			overhead: ttfb,
+			model: self.model.clone(),
+			thinking: request.thinking,
+			thinking_blocks: thinking_block(thinking).into_iter().collect(),
+			stop_reason: candidate.finish_reason,
+			..
+		})
+	}
+
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		let response = self.send(request, true).await?;
+		Ok(sse_events(response))
+	}
+
+	async fn send(&self, request: &Request<'_>, stream: bool) -> Result<reqwest::Response> {
+		let payload = self.payload(request)?;
+		tracing::debug!(?payload);
+
+		let mut headers = HeaderMap::new();
+		let api_key = self.api_key.as_deref().ok_or(Error::MissingCredentials { env_var: "GEMINI_API_KEY" })?;
+		headers.insert(
+			"x-goog-api-key",
+			HeaderValue::from_str(api_key).map_err(|_| Error::Config("Gemini API key is not a valid header value".to_string()))?,
+		);
+		headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+		let base_url = self.base_url.trim_end_matches('/');
+		let url = match stream {
+			true => format!("{base_url}/models/{}:streamGenerateContent?alt=sse", self.model),
+			false => format!("{base_url}/models/{}:generateContent", self.model),
+		};
+		let response = reqwest::Client::new().post(url).headers(headers).json(&payload).send().await?;
+
+		match response.status().is_success() {
+			true => Ok(response),
+			false => Err(Error::from_response(response).await),
+		}
+	}
+
+	fn payload(&self, request: &Request<'_>) -> Result<Value> {
+		if !request.tools.is_empty() {
+			return Err(unsupported_tools());
+		}
+
+		let mut system_parts: Vec<GeminiPart> = Vec::new();
+		let mut contents: Vec<GeminiContent> = Vec::new();
+		for message in &request.conversation.0 {
+			let parts = content_to_parts(&message.content)?;
+			match message.role {
+				Role::System => system_parts.extend(parts),
+				Role::User => contents.push(GeminiContent { role: "user", parts }),
+				Role::Assistant => contents.push(GeminiContent { role: "model", parts }),
+			}
+		}
+
+		// Prepend files to the first user message
+		if !request.files.is_empty()
+			&& let Some(first_user_msg) = contents.iter_mut().find(|c| c.role == "user")
+		{
+			let mut parts: Vec<GeminiPart> = request.files.iter().map(file_to_part).collect();
+			parts.append(&mut first_user_msg.parts);
+			first_user_msg.parts = parts;
+		}
+
+		let mut generation_config = json!({
+			"temperature": request.temperature.unwrap_or(0.0),
+		});
+		let config = generation_config.as_object_mut().unwrap();
+		if let Some(max_tokens) = request.max_tokens {
+			config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+		}
+		if let Some(ref stop_seqs) = request.stop_sequences {
+			config.insert("stopSequences".to_string(), json!(stop_seqs));
+		}
+		if request.force_json || request.json_schema.is_some() {
+			config.insert("responseMimeType".to_string(), json!("application/json"));
+		}
+		if let Some(schema) = request.json_schema {
+			config.insert("responseJsonSchema".to_string(), schema.clone());
+		}
+		let thinking_budget = match request.thinking {
+			// 2.5 Pro can't have thinking disabled, so it keeps its default dynamic budget
+			ThinkingLevel::None if self.model.contains("-pro") => None,
+			// the others think by default, and bill for it, unless told not to
+			ThinkingLevel::None => Some(0),
+			ThinkingLevel::Low => Some(2_048),
+			ThinkingLevel::Medium => Some(8_192),
+			ThinkingLevel::High => Some(24_576), // highest value accepted by every 2.5 model
+		};
+		if let Some(budget) = thinking_budget {
+			config.insert("thinkingConfig".to_string(), json!({"thinkingBudget": budget, "includeThoughts": budget > 0}));
+		}
+
+		let mut payload = json!({
+			"contents": contents,
+			"generationConfig": generation_config,
+		});
+		if !system_parts.is_empty() {
+			payload.as_object_mut().unwrap().insert("systemInstruction".to_string(), json!({ "parts": system_parts }));
+		}
+		Ok(payload)
+	}
+}
+
+fn unsupported_tools() -> Error {
+	Error::UnsupportedContent {
+		backend: "Gemini",
+		detail: "tool calling".to_string(),
+	}
+}
+
+impl Backend for Gemini {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(self.do_conversation(request))
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		format!("gemini:{}", self.model)
+	}
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiContent {
+	role: &'static str,
+	parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum GeminiPart {
+	Text(String),
+	InlineData(InlineData),
+}
+impl GeminiPart {
+	fn inline(media_type: &str, base64_data: &str) -> Self {
+		Self::InlineData(InlineData {
+			mime_type: media_type.to_string(),
+			data: base64_data.to_string(),
+		})
+	}
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+	mime_type: String,
+	data: String,
+}
+
+fn content_to_parts(content: &MessageContent) -> Result<Vec<GeminiPart>> {
+	Ok(match content {
+		MessageContent::Text(text) => vec![GeminiPart::Text(text.clone())],
+		MessageContent::Image { base64_data, media_type } | MessageContent::Document { base64_data, media_type } => vec![GeminiPart::inline(media_type, base64_data)],
+		MessageContent::TextAndImages { text, images } => {
+			let mut parts = vec![GeminiPart::Text(text.clone())];
+			parts.extend(images.iter().map(|img| GeminiPart::inline(&img.media_type, &img.base64_data)));
+			parts
+		}
+		MessageContent::Mixed { parts } => parts
+			.iter()
+			.map(|part| match part {
+				ContentPart::Text(text) => GeminiPart::Text(text.clone()),
+				ContentPart::Image { base64_data, media_type } | ContentPart::Document { base64_data, media_type } => GeminiPart::inline(media_type, base64_data),
+			})
+			.collect(),
+		MessageContent::ToolUse { .. } | MessageContent::ToolResults(_) => return Err(unsupported_tools()),
+	})
+}
+
+/// Gemini reads PDFs and images natively; other files are decoded and inlined as text, same as the Claude backend.
+fn file_to_part(file: &FileAttachment) -> GeminiPart {
+	use base64::Engine;
+	match file.media_type.as_str() {
+		mt if mt == "application/pdf" || mt.starts_with("image/") => GeminiPart::inline(&file.media_type, &file.base64_data),
+		_ => {
+			let decoded = base64::engine::general_purpose::STANDARD
+				.decode(&file.base64_data)
+				.ok()
+				.and_then(|bytes| String::from_utf8(bytes).ok())
+				.unwrap_or_else(|| format!("[Binary file: {}]", file.media_type));
+			GeminiPart::Text(decoded)
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+	#[serde(default)]
+	candidates: Vec<Candidate>,
+	prompt_feedback: Option<PromptFeedback>,
+	usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+	content: Option<CandidateContent>,
+	finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CandidateContent {
+	#[serde(default)]
+	parts: Vec<CandidatePart>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CandidatePart {
+	text: Option<String>,
+	#[serde(default)]
+	thought: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+	block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+	#[serde(default)]
+	prompt_token_count: u32,
+	#[serde(default)]
+	candidates_token_count: u32,
+	#[serde(default)]
+	thoughts_token_count: u32,
+	#[serde(default)]
+	cached_content_token_count: u32,
+}
+impl From<UsageMetadata> for Usage {
+	fn from(usage: UsageMetadata) -> Self {
+		// `promptTokenCount` includes the cached ones
+		Self {
+			input: usage.prompt_token_count.saturating_sub(usage.cached_content_token_count),
+			output: usage.candidates_token_count + usage.thoughts_token_count,
+			cache_read: usage.cached_content_token_count,
+			cache_write: 0,
+			thinking: usage.thoughts_token_count,
+		}
+	}
+}
+
+/// Finish reasons for a reply stopped by Gemini's filters
+fn is_blocked(finish_reason: &str) -> bool {
+	matches!(finish_reason, "SAFETY" | "PROHIBITED_CONTENT" | "BLOCKLIST" | "SPII")
+}
+
+/// Thought summaries, which come without signatures.
+fn thinking_block(thinking: String) -> Option<ThinkingBlock> {
+	(!thinking.is_empty()).then_some(ThinkingBlock::Thinking { text: thinking, signature: None })
+}
+
+/// Turn a `streamGenerateContent?alt=sse` response into [StreamEvent]s. Each event carries a full `GenerateContentResponse`.
+fn sse_events(response: reqwest::Response) -> ResponseStream {
+	struct State {
+		thinking: String,
+	}
+	impl crate::sse::Translate for State {
+		fn event(&mut self, raw: crate::sse::Event, out: &mut Vec<StreamEvent>) -> Result<()> {
+			let chunk: GenerateContentResponse = serde_json::from_str(&raw.data)?;
+			if let Some(reason) = chunk.prompt_feedback.and_then(|f| f.block_reason) {
+				return Err(Error::Refusal(reason));
+			}
+			let mut stop = None;
+			for candidate in chunk.candidates {
+				for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+					match (part.text, part.thought) {
+						(Some(text), true) => {
+							self.thinking.push_str(&text);
+							out.push(StreamEvent::Thinking(text));
+						}
+						(Some(text), false) if !text.is_empty() => out.push(StreamEvent::Text(text)),
+						_ => {}
+					}
+				}
+				if let Some(reason) = candidate.finish_reason.as_deref().filter(|r| is_blocked(r)) {
+					return Err(Error::Refusal(reason.to_string()));
+				}
+				if candidate.finish_reason.is_some() {
+					stop = candidate.finish_reason;
+				}
+			}
+			if stop.is_some()
+				&& let Some(block) = thinking_block(std::mem::take(&mut self.thinking))
+			{
+				out.push(StreamEvent::ThinkingBlock(block));
+			}
+			if let Some(usage) = chunk.usage_metadata {
+				out.push(StreamEvent::Usage(usage.into()));
+			}
+			if stop.is_some() {
+				out.push(StreamEvent::Stop { reason: stop, stop_sequence: None });
+			}
+			Ok(())
+		}
+	}
+
+	crate::sse::events(response.bytes_stream(), State { thinking: String::new() })
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::StreamExt as _;
+
+	use super::*;
+	use crate::{
+		Conversation,
+		testing::{self, fake_server},
+	};
+
+	fn request(conv: &Conversation) -> Request<'_> {
+		Request {
+			max_tokens: Some(64),
+			force_json: true,
+			thinking: ThinkingLevel::Low,
+			..testing::request(conv)
+		}
+	}
+
+	#[tokio::test]
+	async fn generate_content() {
+		let (url, server) = fake_server(
+			"application/json",
+			r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"hmm","thought":true},{"text":"{\"a\":1}"}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":5,"candidatesTokenCount":3}}"#,
+		)
+		.await;
+		let backend = Gemini {
+			api_key: Some("test".to_string()),
+			model: "gemini-2.5-flash".to_string(),
+			base_url: format!("{url}/v1beta"),
+		};
+		let mut conv = Conversation::new_with_system("be terse");
+		conv.add_exchange("hi", "hello");
+		conv.add(Role::User, "json please");
+
+		let response = backend.do_conversation(&request(&conv)).await.unwrap();
+		assert_eq!(response.text, r#"{"a":1}"#);
+		assert_eq!((response.usage.input, response.usage.output), (5, 3));
+		assert!(matches!(&response.thinking_blocks[..], [ThinkingBlock::Thinking { text, signature: None }] if text == "hmm"));
+
+		let received = server.await.unwrap();
+		assert!(received.starts_with("POST /v1beta/models/gemini-2.5-flash:generateContent "));
+		let body: Value = serde_json::from_str(received.split_once("\r\n\r\n").unwrap().1).unwrap();
+		assert_eq!(body["systemInstruction"], json!({"parts": [{"text": "be terse"}]}));
+		assert_eq!(body["contents"][1], json!({"role": "model", "parts": [{"text": "hello"}]}));
+		assert_eq!(body["generationConfig"]["responseMimeType"], "application/json");
+		assert_eq!(body["generationConfig"]["thinkingConfig"]["thinkingBudget"], 2_048);
+	}
+
+	#[tokio::test]
+	async fn stream_generate_content() {
+		let (url, server) = fake_server(
+			"text/event-stream",
+			"data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"hmm\",\"thought\":true},{\"text\":\"Hel\"}]}}]}\r\n\r\ndata: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"lo\"}]},\"finishReason\":\"STOP\"}],\"usageMetadata\":{\"promptTokenCount\":2,\"candidatesTokenCount\":2}}\r\n\r\n",
+		)
+		.await;
+		let backend = Gemini {
+			api_key: Some("test".to_string()),
+			model: "gemini-2.5-flash".to_string(),
+			base_url: format!("{url}/v1beta"),
+		};
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "hi");
+
+		let events: Vec<StreamEvent> = backend.do_stream(&request(&conv)).await.unwrap().map(Result::unwrap).collect().await;
+		let text: String = events
+			.iter()
+			.filter_map(|e| match e {
+				StreamEvent::Text(t) => Some(t.as_str()),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(text, "Hello");
+		assert!(matches!(&events[0], StreamEvent::Thinking(t) if t == "hmm"));
+		assert!(matches!(&events[events.len() - 3], StreamEvent::ThinkingBlock(ThinkingBlock::Thinking { text, .. }) if text == "hmm"));
+		assert!(matches!(events.last(), Some(StreamEvent::Stop { reason: Some(r), .. }) if r == "STOP"));
+
+		let received = server.await.unwrap();
+		assert!(received.starts_with("POST /v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse "));
+	}
+
+	#[test]
+	fn thinking_off_where_possible() {
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "hi");
+		let request = Request {
+			thinking: ThinkingLevel::None,
+			..request(&conv)
+		};
+		let budget = |model: &str| {
+			let backend = Gemini {
+				api_key: None,
+				model: model.to_string(),
+				base_url: String::new(),
+			};
+			backend.payload(&request).unwrap()["generationConfig"].get("thinkingConfig").cloned()
+		};
+		assert_eq!(budget("gemini-2.5-flash"), Some(json!({"thinkingBudget": 0, "includeThoughts": false})));
+		assert_eq!(budget("gemini-2.5-pro"), None);
+	}
+
+	#[tokio::test]
+	async fn stream_blocked() {
+		let (url, _server) = fake_server(
+			"text/event-stream",
+			"data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Sure\"}]}}]}\r\n\r\ndata: {\"candidates\":[{\"finishReason\":\"SAFETY\"}]}\r\n\r\n",
+		)
+		.await;
+		let backend = Gemini {
+			api_key: Some("test".to_string()),
+			model: "gemini-2.5-flash".to_string(),
+			base_url: format!("{url}/v1beta"),
+		};
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "hi");
+
+		let events: Vec<_> = backend.do_stream(&request(&conv)).await.unwrap().collect().await;
+		assert!(matches!(&events[0], Ok(StreamEvent::Text(t)) if t == "Sure"));
+		assert!(matches!(&events[1], Err(Error::Refusal(r)) if r == "SAFETY"));
+	}
+}
@@ -1,10 +1,28 @@
 #![feature(default_field_values)]
-use std::{future::Future, pin::Pin};
+use std::{future::Future, pin::Pin, sync::Arc};
 
-use eyre::{Result, bail};
+pub use budget::{Budget, Ledger, LedgerEntry};
+pub use context::ContextStrategy;
+pub use disk_cache::DiskCache;
+pub use error::{Error, Result};
+pub use fallback::Skipped;
+use futures::{Stream, StreamExt};
+pub use retry::{MidStreamPolicy, RetryPolicy};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
+mod budget;
 mod claude;
+mod context;
+mod disk_cache;
+mod error;
+mod fallback;
+mod gemini;
 mod ollama;
+mod openai;
+mod prefill;
+mod retry;
+mod sse;
 
 impl Client {
 	/// Create a new client using default config (reads from environment).
@@ -19,14 +37,64 @@ impl Client {
 			force_json: false,
 			files: Vec::new(),
 			thinking: ThinkingLevel::default(),
+			tools: Vec::new(),
+			retry: RetryPolicy::default(),
+			disk_cache: None,
+			spending: budget::Spending::default(),
+			context_strategy: ContextStrategy::default(),
+			auto_continue: 0,
+			prefill: None,
 		}
 	}
 
+	/// Send requests to your own [Backend] instead of a [Model] tier. All other options work as usual; `config` is still used by
+	/// [fallback](#method.fallback), [model](#method.model) and [ContextStrategy::Summarize].
+	pub fn with_backend(config: config::AppConfig, backend: impl Backend + 'static) -> Self {
+		Self {
+			backend: Box::new(backend),
+			..Self::new(config)
+		}
+	}
+
+	/// Record exchanges with the backend chosen so far to `path` and replay them, per `mode`; see [testing::FixtureMode].
+	/// Call after [model](#method.model) and [fallback](#method.fallback). Fails if `path` exists but isn't a fixture.
+	pub fn fixture(mut self, path: impl Into<std::path::PathBuf>, mode: testing::FixtureMode) -> Result<Self> {
+		self.backend = Box::new(testing::Fixture::new(self.backend, path.into(), mode)?);
+		Ok(self)
+	}
+
+	/// On retryable errors, missing credentials or content it can't handle, pass the request from the backend chosen so far on to `models`, in order.
+	/// Call after [model](#method.model) / [gemini](#method.gemini) / [openai_compatible](#method.openai_compatible), which replace the whole chain.
+	pub fn fallback(mut self, models: impl IntoIterator<Item = Model>) -> Self {
+		let mut backends = vec![self.backend];
+		backends.extend(models.into_iter().map(|model| model.into_backend(&self.config)));
+		self.backend = Box::new(fallback::Fallback { backends });
+		self
+	}
+
+	/// Pick a tier; which provider and model serve it can be overridden in [config::AppConfig::models].
 	pub fn model(mut self, model: Model) -> Self {
 		self.backend = model.into_backend(&self.config);
 		self
 	}
 
+	/// Send requests to an OpenAI Chat Completions compatible server (OpenAI, OpenRouter, Groq, vLLM, llama.cpp server, LM Studio...) instead of a [Model] tier.
+	/// `base_url` is everything before `/chat/completions`, eg `https://api.openai.com/v1` or `http://localhost:1234/v1`.
+	pub fn openai_compatible(mut self, base_url: impl Into<String>, api_key: Option<String>, model: impl Into<String>) -> Self {
+		self.backend = Box::new(openai::OpenAi {
+			base_url: base_url.into(),
+			api_key,
+			model: model.into(),
+		});
+		self
+	}
+
+	/// Send requests to Google Gemini instead of a [Model] tier, eg `gemini-2.5-flash`.
+	pub fn gemini(mut self, model: impl Into<String>) -> Self {
+		self.backend = config::ModelSpec::new(config::Provider::Gemini, model).backend(&self.config);
+		self
+	}
+
 	pub fn temperature(mut self, temperature: f32) -> Self {
 		self.temperature = Some(temperature);
 		self
@@ -52,12 +120,86 @@ impl Client {
 		self
 	}
 
+	/// Declare tools the model may call. Their calls are reported in [Response::tool_calls]; use
+	/// [ask_with_tools](#method.ask_with_tools) to have them executed automatically.
+	pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+		self.tools = tools;
+		self
+	}
+
+	/// Retry rate limits, overload and network failures according to `policy`. Defaults to [RetryPolicy::default]; use [RetryPolicy::none] to opt out.
+	pub fn retry(mut self, policy: RetryPolicy) -> Self {
+		self.retry = policy;
+		self
+	}
+
+	/// Reuse replies to identical requests from an on-disk cache instead of paying for them again; hits have [Response::cache_hit] set.
+	pub fn disk_cache(mut self, cache: DiskCache) -> Self {
+		self.disk_cache = Some(cache);
+		self
+	}
+
+	/// Refuse requests that could take spending over `budget`, with [Error::BudgetExceeded].
+	pub fn budget(mut self, budget: Budget) -> Self {
+		self.spending.budget = budget;
+		self
+	}
+
+	/// Append every reply except cache hits to `ledger`; its entries for today also count towards [Budget::daily_cents].
+	/// Streams from [conversation_stream](#method.conversation_stream) are checked against the budget but not recorded, as the client doesn't see them end.
+	pub fn ledger(mut self, ledger: Ledger) -> Self {
+		self.spending.ledger = Some(ledger);
+		self
+	}
+
+	/// Trim or summarize conversations before sending, so they fit into the context window. Defaults to [ContextStrategy::None].
+	pub fn context_strategy(mut self, strategy: ContextStrategy) -> Self {
+		self.context_strategy = strategy;
+		self
+	}
+
+	/// When a reply stops at the [max_tokens](#method.max_tokens) limit, ask for the rest up to `max_rounds` more times, continuing from
+	/// the reply so far, and return the pieces stitched together. Cost, usage and duration cover all rounds. Streams aren't continued.
+	pub fn auto_continue(mut self, max_rounds: usize) -> Self {
+		self.auto_continue = max_rounds;
+		self
+	}
+
+	/// Start every reply with `text`, eg `<answer>` or a codeblock fence, to steer its format; see [Conversation::prefill].
+	/// Streams yield it as their first [Text](StreamEvent::Text).
+	pub fn prefill(mut self, text: impl Into<String>) -> Self {
+		self.prefill = Some(text.into());
+		self
+	}
+
+	/// Input tokens `conv` takes up with the current files, tools and thinking settings. Exact for Claude, a rough local estimate for other providers.
+	pub async fn count_tokens(&self, conv: &Conversation) -> Result<u32> {
+		self.backend.count_tokens(&self.request(conv)).await
+	}
+
+	/// Spent by this client so far, cache hits excluded.
+	pub fn session_cost_cents(&self) -> f32 {
+		self.spending.session_cents()
+	}
+
 	/// Append a file to be included with the request.
 	/// Supported media types: application/pdf, text/plain, text/markdown, text/csv,
 	/// application/vnd.openxmlformats-officedocument.wordprocessingml.document (docx),
 	/// application/vnd.openxmlformats-officedocument.spreadsheetml.sheet (xlsx)
 	pub fn append_file(mut self, base64_data: String, media_type: String) -> Self {
-		self.files.push(FileAttachment { base64_data, media_type });
+		self.files.push(FileAttachment {
+			base64_data,
+			media_type,
+			cache: false,
+		});
+		self
+	}
+
+	/// Cache the files attached so far (and the system prompt before them), so repeated requests over the same documents are billed at the cache-read rate.
+	pub fn cache_files(mut self) -> Self {
+		if let Some(last) = self.files.last_mut() {
+			last.cache = true;
+		}
 		self
 	}
 
@@ -77,55 +219,333 @@ impl Client {
 	}
 
 	pub async fn conversation(&self, conv: &Conversation) -> Result<Response> {
+		let fitted = context::fit(self, conv).await?;
+		let mut response = self.send(&self.request(&fitted.conv)).await?;
+		fitted.cost.charge(&mut response);
+		Ok(response)
+	}
+
+	/// Ask for a reply deserialized into `T`. See [conversation_typed](#method.conversation_typed).
+	pub async fn ask_typed<T: DeserializeOwned + JsonSchema>(&self, message: impl Into<String>) -> Result<T> {
+		let mut conv = Conversation::new();
+		conv.add(Role::User, message.into());
+		self.conversation_typed(&conv).await
+	}
+
+	/// Constrain the reply to the JSON schema of `T` (Claude structured outputs, Ollama/OpenAI/Gemini schema formats) and deserialize it.
+	/// If the reply still doesn't fit, the error is fed back to the model and the request retried.
+	pub async fn conversation_typed<T: DeserializeOwned + JsonSchema>(&self, conv: &Conversation) -> Result<T> {
+		const MAX_ATTEMPTS: usize = 3;
+
+		let schema = schemars::schema_for!(T).to_value();
+		let mut conv = conv.clone();
+		let mut last_error = None;
+		for _ in 0..MAX_ATTEMPTS {
+			let fitted = context::fit(self, &conv).await?;
+			let mut request = self.request(&fitted.conv);
+			request.json_schema = Some(&schema);
+			let response = self.send(&request).await?;
+			match serde_json::from_str::<T>(&response.text) {
+				Ok(value) => return Ok(value),
+				Err(e) => {
+					tracing::warn!("Typed response failed validation: {e}");
+					conv.add_response(&response);
+					conv.add(
+						Role::User,
+						format!("That response failed validation: {e}\nReply again with only the corrected JSON, matching the schema exactly."),
+					);
+					last_error = Some(e);
+				}
+			}
+		}
+		Err(Error::InvalidResponse(format!("No valid response after {MAX_ATTEMPTS} attempts: {}", last_error.unwrap())))
+	}
+
+	/// Like [ask](#method.ask), but executes any tool calls the model makes and feeds the results back, until it replies without calling tools.
+	pub async fn ask_with_tools(&self, message: impl Into<String>) -> Result<Response> {
+		let mut conv = Conversation::new();
+		conv.add(Role::User, message.into());
+		self.conversation_with_tools(&mut conv).await
+	}
+
+	/// Tool loop over an existing conversation. Tool calls and their results are appended to `conv`, the final reply is not.
+	/// Cost and duration of the returned [Response] cover all rounds.
+	pub async fn conversation_with_tools(&self, conv: &mut Conversation) -> Result<Response> {
+		const MAX_ROUNDS: usize = 32;
+
+		let mut cost_cents = 0.0;
+		let mut usage = Usage::default();
+		let mut duration = std::time::Duration::ZERO;
+		for _ in 0..MAX_ROUNDS {
+			let mut response = self.conversation(conv).await?;
+			cost_cents += response.cost_cents;
+			usage += response.usage;
+			duration += response.duration;
+			if response.tool_calls.is_empty() {
+				response.cost_cents = cost_cents;
+				response.usage = usage;
+				response.duration = duration;
+				return Ok(response);
+			}
+
+			// with its thinking blocks, which Claude requires back while the tool loop runs
+			conv.add_response(&response);
+			let results = futures::future::join_all(response.tool_calls.iter().map(|call| async move {
+				let outcome = match self.tools.iter().find(|t| t.name == call.name) {
+					Some(tool) => tool.call(call.input.clone()).await,
+					None => Err(eyre::eyre!("Unknown tool: {}", call.name)),
+				};
+				match outcome {
+					Ok(content) => ToolResult {
+						tool_use_id: call.id.clone(),
+						content,
+						is_error: false,
+					},
+					Err(e) => ToolResult {
+						tool_use_id: call.id.clone(),
+						content: e.to_string(),
+						is_error: true,
+					},
+				}
+			}))
+			.await;
+
+			conv.0.push(Message::new_tool_results(results));
+		}
+		Err(Error::InvalidResponse(format!("Model kept calling tools after {MAX_ROUNDS} rounds")))
+	}
+
+	/// Like [ask](#method.ask), but yields the reply incrementally as it is generated.
+	pub async fn ask_stream(&self, message: impl Into<String>) -> Result<ResponseStream> {
+		let mut conv = Conversation::new();
+		conv.add(Role::User, message.into());
+		self.conversation_stream(&conv).await
+	}
+
+	/// Like [conversation](#method.conversation), but yields the reply incrementally as it is generated.
+	/// The returned stream does not borrow the client or the conversation.
+	pub async fn conversation_stream(&self, conv: &Conversation) -> Result<ResponseStream> {
+		Ok(self.open_stream(conv).await?.0)
+	}
+
+	/// [conversation_stream](#method.conversation_stream), and what fitting the conversation cost.
+	async fn open_stream(&self, conv: &Conversation) -> Result<(ResponseStream, context::FitCost)> {
+		let context::Fitted { mut conv, cost } = context::fit(self, conv).await?;
+		if let Some(prefix) = &self.prefill {
+			conv.to_mut().prefill(prefix);
+		}
+		let native = self.backend.native_prefill(&self.request(&conv));
+		let (conv, prefix) = match prefill::prepare(&conv, native) {
+			Some((prepared, prefix)) => (std::borrow::Cow::Owned(prepared), Some(prefix)),
+			None => (conv, None),
+		};
+		let request = &self.request(&conv);
+		self.spending.check(request, self.backend.as_ref())?;
+		let backend = &self.backend;
+		let mid_stream = self.retry.mid_stream;
+		let stream = self
+			.retry
+			.run(|| async move {
+				let mut stream = backend.stream(request).await?.fuse();
+				if mid_stream == MidStreamPolicy::Propagate {
+					return Ok(Box::pin(stream) as ResponseStream);
+				}
+				// hold events back until the first output, so a failure before it can still be retried
+				let mut head = Vec::new();
+				while let Some(event) = stream.next().await {
+					let event = event?;
+					let is_output = matches!(event, StreamEvent::Text(_) | StreamEvent::Thinking(_) | StreamEvent::ToolUse(_) | StreamEvent::Stop { .. });
+					head.push(Ok(event));
+					if is_output {
+						break;
+					}
+				}
+				Ok(Box::pin(futures::stream::iter(head).chain(stream)) as ResponseStream)
+			})
+			.await?;
+		let stream = match prefix {
+			Some(prefix) => prefill::join_stream(prefix, stream, native),
+			None => stream,
+		};
+		Ok((stream, cost))
+	}
+
+	/// Streams like [conversation_stream](#method.conversation_stream), handing each event to `on_event` as it arrives, and assembles the [Response] from them.
+	pub async fn conversation_streamed(&self, conv: &Conversation, mut on_event: impl FnMut(&StreamEvent)) -> Result<Response> {
+		let start = std::time::Instant::now();
+		let (mut stream, fit_cost) = self.open_stream(conv).await?;
+		let mut overhead = None;
+		let mut text = String::new();
+		let mut usage = Usage::default();
+		let mut tool_calls = Vec::new();
+		let mut thinking_blocks = Vec::new();
+		let mut stop = (None, None);
+		let mut backend = None;
+		let mut skipped = Vec::new();
+		while let Some(event) = stream.next().await {
+			let event = event?;
+			on_event(&event);
+			match event {
+				StreamEvent::Text(t) => {
+					overhead.get_or_insert_with(|| start.elapsed());
+					text.push_str(&t);
+				}
+				StreamEvent::Thinking(_) => {
+					overhead.get_or_insert_with(|| start.elapsed());
+				}
+				StreamEvent::Usage(u) => usage = u,
+				StreamEvent::ToolUse(call) => tool_calls.push(call),
+				StreamEvent::ThinkingBlock(block) => thinking_blocks.push(block),
+				StreamEvent::Stop { reason, stop_sequence } => stop = (reason, stop_sequence),
+				StreamEvent::Backend { name, skipped: passed_over } => {
+					backend = Some(name);
+					skipped.extend(passed_over);
+				}
+			}
+		}
+		let backend = backend.unwrap_or_else(|| self.backend.name());
+		let mut response = Response {
+			text,
+			cost_cents: self.backend.stream_cost_cents(&backend, &usage),
+			usage,
+			duration: start.elapsed(),
+			overhead: overhead.unwrap_or_default(),
+			model: backend.split_once(':').map_or(backend.as_str(), |(_, model)| model).to_string(),
+			thinking: self.thinking,
+			tool_calls,
+			backend,
+			skipped,
+			thinking_blocks,
+			stop_reason: stop.0,
+			stop_sequence: stop.1,
+			..
+		};
+		self.spending.record(&response);
+		fit_cost.charge(&mut response);
+		Ok(response)
+	}
+
+	async fn send(&self, request: &Request<'_>) -> Result<Response> {
+		let mut conv = std::borrow::Cow::Borrowed(request.conversation);
+		if let Some(prefix) = &self.prefill {
+			conv.to_mut().prefill(prefix);
+		}
+		let mut response = self.send_prefilled(&request.with_conversation(&conv)).await?;
+		for _ in 0..self.auto_continue {
+			if !response.is_truncated() {
+				break;
+			}
+			conv.to_mut().prefill(&response.text);
+			let rest = self.send_prefilled(&request.with_conversation(&conv)).await?;
+			response = prefill::stitch(response, rest);
+		}
+		Ok(response)
+	}
+
+	/// Sends with a prefill the conversation may end in, emulated if the backend has none, and re-joins the prefix.
+	async fn send_prefilled(&self, request: &Request<'_>) -> Result<Response> {
+		let native = self.backend.native_prefill(request);
+		let Some((conv, prefix)) = prefill::prepare(request.conversation, native) else {
+			return self.send_once(request).await;
+		};
+		let mut response = self.send_once(&request.with_conversation(&conv)).await?;
+		response.text = prefill::join(&prefix, &response.text, native);
+		Ok(response)
+	}
+
+	async fn send_once(&self, request: &Request<'_>) -> Result<Response> {
+		self.send_via(self.backend.as_ref(), request).await
+	}
+
+	/// One call to `backend`, with this client's cache, budget, ledger and retries.
+	async fn send_via(&self, backend: &dyn Backend, request: &Request<'_>) -> Result<Response> {
+		let start = std::time::Instant::now();
+		let cache_key = self.disk_cache.as_ref().map(|_| DiskCache::key(&backend.name(), request));
+		if let (Some(cache), Some(key)) = (&self.disk_cache, &cache_key)
+			&& let Some(mut response) = cache.get(key)
+		{
+			response.duration = start.elapsed();
+			response.backend = backend.name();
+			return Ok(response);
+		}
+
+		self.spending.check(request, backend)?;
+		let mut response = self.retry.run(|| backend.conversation(request)).await?;
+		response.duration = start.elapsed();
+		if response.backend.is_empty() {
+			response.backend = backend.name();
+		}
+		if let (Some(cache), Some(key)) = (&self.disk_cache, &cache_key) {
+			cache.put(key, &response);
+		}
+		self.spending.record(&response);
+		Ok(response)
+	}
+
+	fn request<'a>(&'a self, conv: &'a Conversation) -> Request<'a> {
 		let stop_seqs: Option<Vec<&str>> = self.stop_sequences.as_ref().map(|v| v.iter().map(|s| s.as_str()).collect());
-		let request = Request {
+		Request {
 			conversation: conv,
 			temperature: self.temperature,
 			max_tokens: self.max_tokens,
 			stop_sequences: stop_seqs,
 			force_json: self.force_json,
+			json_schema: None,
 			files: &self.files,
 			thinking: self.thinking,
-		};
-		let start = std::time::Instant::now();
-		let mut response = self.backend.conversation(&request).await?;
-		response.duration = start.elapsed();
-		Ok(response)
+			tools: &self.tools,
+		}
 	}
 }
 
 impl Model {
 	fn into_backend(self, config: &config::AppConfig) -> Box<dyn Backend> {
+		match config.models.get(self) {
+			Some(spec) => spec.backend(config),
+			None => self.default_spec().backend(config),
+		}
+	}
+
+	/// Used for tiers not overridden in [config::AppConfig::models]
+	fn default_spec(self) -> config::ModelSpec {
+		use config::{ModelSpec, Provider};
 		match self {
-			Model::Cheap => Box::new(ollama::Ollama {
-				model: "qwen3.5:4b".to_string(),
-				url: "http://localhost:11434/api/chat".to_string(),
+			Model::Cheap => ModelSpec::new(Provider::Ollama, "qwen3.5:4b"),
+			Model::Translate => ModelSpec::new(Provider::Ollama, "translategemma:4b"),
+			Model::Fast => ModelSpec::new(Provider::Claude, "claude-haiku-4-5"),
+			Model::Medium => ModelSpec::new(Provider::Claude, "claude-sonnet-4-5"),
+			Model::Slow => ModelSpec::new(Provider::Claude, "claude-opus-4-1"),
+		}
+	}
+}
+
+impl config::ModelSpec {
+	fn backend(&self, config: &config::AppConfig) -> Box<dyn Backend> {
+		let endpoint = self.endpoint_or_default();
+		match self.provider {
+			config::Provider::Claude => Box::new(claude::Claude {
+				api_key: claude_api_key(config),
+				model_id: self.model.clone(),
+				model: self.model.parse().unwrap_or_else(|e| {
+					tracing::warn!("{e}, pricing it as Sonnet");
+					claude::ClaudeModel::Sonnet45
+				}),
+				url: format!("{endpoint}/v1/messages"),
 			}),
-			Model::Translate => Box::new(ollama::Ollama {
-				model: "translategemma:4b".to_string(),
-				url: "http://localhost:11434/api/chat".to_string(),
+			config::Provider::Ollama => Box::new(ollama::Ollama {
+				model: self.model.clone(),
+				url: format!("{endpoint}/api/chat"),
+			}),
+			config::Provider::OpenAi => Box::new(openai::OpenAi {
+				base_url: endpoint.to_string(),
+				api_key: config.openai_token.clone().or_else(|| std::env::var("OPENAI_API_KEY").ok()),
+				model: self.model.clone(),
+			}),
+			config::Provider::Gemini => Box::new(gemini::Gemini {
+				api_key: gemini_api_key(config),
+				model: self.model.clone(),
+				base_url: endpoint.to_string(),
 			}),
-			Model::Fast => {
-				let api_key = claude_api_key(config);
-				Box::new(claude::Claude {
-					api_key,
-					model: claude::ClaudeModel::Haiku45,
-				})
-			}
-			Model::Medium => {
-				let api_key = claude_api_key(config);
-				Box::new(claude::Claude {
-					api_key,
-					model: claude::ClaudeModel::Sonnet45,
-				})
-			}
-			Model::Slow => {
-				let api_key = claude_api_key(config);
-				Box::new(claude::Claude {
-					api_key,
-					model: claude::ClaudeModel::Opus41,
-				})
-			}
 		}
 	}
 }
@@ -135,6 +555,8 @@ impl Message {
 		Self {
 			role,
 			content: MessageContent::Text(content.into()),
+			cache: false,
+			thinking: Vec::new(),
 		}
 	}
 
@@ -142,13 +564,79 @@ impl Message {
 		Self {
 			role,
 			content: MessageContent::Image { base64_data, media_type },
+			cache: false,
+			thinking: Vec::new(),
 		}
 	}
 
+	/// Image (by extension) or document from a filesystem path, eg for attaching a pdf to a conversation.
+	pub fn new_with_file_from_path(role: Role, path: impl AsRef<std::path::Path>) -> Result<Self> {
+		let path = path.as_ref();
+		let data = std::fs::read(path)?;
+		let base64_data = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &data);
+		let media_type = mime_type_from_extension(path.extension().and_then(|s| s.to_str()).unwrap_or("")).to_string();
+		let content = match media_type.starts_with("image/") {
+			true => MessageContent::Image { base64_data, media_type },
+			false => MessageContent::Document { base64_data, media_type },
+		};
+		Ok(Self {
+			role,
+			content,
+			cache: false,
+			thinking: Vec::new(),
+		})
+	}
+
 	pub fn new_with_text_and_images(role: Role, text: String, images: Vec<ImageContent>) -> Self {
 		Self {
 			role,
 			content: MessageContent::TextAndImages { text, images },
+			cache: false,
+			thinking: Vec::new(),
+		}
+	}
+
+	/// Assistant turn that called tools, with whatever text the model wrote alongside.
+	pub fn new_tool_use(text: String, calls: Vec<ToolCall>) -> Self {
+		Self {
+			role: Role::Assistant,
+			content: MessageContent::ToolUse { text, calls },
+			cache: false,
+			thinking: Vec::new(),
+		}
+	}
+
+	/// Mark a prompt cache breakpoint: providers that need explicit markers (Claude) cache the conversation up to and including this message.
+	/// Others cache repeated prefixes on their own.
+	pub fn cached(mut self) -> Self {
+		self.cache = true;
+		self
+	}
+
+	pub fn role(&self) -> Role {
+		self.role
+	}
+
+	pub fn content(&self) -> &MessageContent {
+		&self.content
+	}
+
+	/// Whether this is a prompt cache breakpoint, see [cached](#method.cached).
+	pub fn is_cached(&self) -> bool {
+		self.cache
+	}
+
+	/// Reasoning that preceded this assistant turn.
+	pub fn thinking(&self) -> &[ThinkingBlock] {
+		&self.thinking
+	}
+
+	pub fn new_tool_results(results: Vec<ToolResult>) -> Self {
+		Self {
+			role: Role::User,
+			content: MessageContent::ToolResults(results),
+			cache: false,
+			thinking: Vec::new(),
 		}
 	}
 }
@@ -170,9 +658,86 @@ impl Conversation {
 		self.add(Role::User, user_message);
 		self.add(Role::Assistant, assistant_message);
 	}
+
+	/// Append a reply as the assistant turn, with its tool calls and thinking blocks, so multi-turn extended thinking keeps working.
+	pub fn add_response(&mut self, response: &Response) {
+		let content = match response.tool_calls.is_empty() {
+			true => MessageContent::Text(response.text.clone()),
+			false => MessageContent::ToolUse {
+				text: response.text.clone(),
+				calls: response.tool_calls.clone(),
+			},
+		};
+		self.0.push(Message {
+			role: Role::Assistant,
+			content,
+			cache: false,
+			thinking: response.thinking_blocks.clone(),
+		});
+	}
+
+	/// Start the reply with `text`, for the model to continue from. The reply's [text](Response::text) includes it.
+	/// Backends without native prefill (all but Claude, which also lacks it with thinking or a JSON schema) are asked to continue instead.
+	/// Replaces an earlier prefill; a conversation ending in an assistant message counts as one.
+	pub fn prefill(&mut self, text: impl Into<String>) {
+		if let Some(Message {
+			role: Role::Assistant,
+			content: MessageContent::Text(_),
+			..
+		}) = self.0.last()
+		{
+			self.0.pop();
+		}
+		self.add(Role::Assistant, text);
+	}
+
+	/// Cache everything up to the last message so far, eg a long system prompt, see [Message::cached].
+	pub fn cache_breakpoint(&mut self) {
+		if let Some(last) = self.0.last_mut() {
+			last.cache = true;
+		}
+	}
+}
+
+impl Serialize for Conversation {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		#[derive(Serialize)]
+		struct Versioned<'a> {
+			version: u32,
+			messages: &'a [Message],
+		}
+		Versioned {
+			version: CONVERSATION_FORMAT_VERSION,
+			messages: &self.0,
+		}
+		.serialize(serializer)
+	}
+}
+impl<'de> Deserialize<'de> for Conversation {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		#[derive(Deserialize)]
+		struct Versioned {
+			version: u32,
+			messages: Vec<Message>,
+		}
+		let versioned = Versioned::deserialize(deserializer)?;
+		if versioned.version != CONVERSATION_FORMAT_VERSION {
+			return Err(serde::de::Error::custom(format!(
+				"unsupported conversation format version {} (expected {CONVERSATION_FORMAT_VERSION})",
+				versioned.version
+			)));
+		}
+		Ok(Self(versioned.messages))
+	}
 }
 
 impl Response {
+	/// Whether generation was cut off by the output token limit, see [Client::auto_continue].
+	pub fn is_truncated(&self) -> bool {
+		// Claude, OpenAI and Ollama, Gemini
+		matches!(self.stop_reason.as_deref(), Some("max_tokens" | "length" | "MAX_TOKENS"))
+	}
+
 	/// Extract codeblocks with optional extension filtering.
 	/// If extensions is None or empty, all codeblocks are returned.
 	/// Extensions are tried in reverse sorted order (longer extensions first).
@@ -219,73 +784,164 @@ impl Response {
 		if blocks.len() == 1 {
 			Ok(blocks.into_iter().next().unwrap())
 		} else {
-			bail!("No codeblocks found or more than one codeblock found.")
+			Err(Error::InvalidResponse("No codeblocks found or more than one codeblock found.".to_string()))
 		}
 	}
 
 	pub fn extract_html_tag(&self, tag_name: &str) -> Result<String> {
 		let opening_tag = format!("<{tag_name}>");
 		let closing_tag = format!("</{tag_name}>");
-		let from_start = self.text.split_once(&opening_tag).unwrap().1;
-		let extracted = from_start.split_once(&closing_tag).unwrap().0;
+		let extracted = self
+			.text
+			.split_once(&opening_tag)
+			.and_then(|(_, from_start)| from_start.split_once(&closing_tag))
+			.map(|(extracted, _)| extracted)
+			.ok_or_else(|| Error::InvalidResponse(format!("No {opening_tag}...{closing_tag} in response")))?;
 		Ok(extracted.to_string())
 	}
 }
 
 pub mod config;
 mod shortcuts;
+pub mod testing;
 pub use shortcuts::*;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Response {
 	pub text: String,
 	pub cost_cents: f32,
+	pub usage: Usage,
 	pub duration: std::time::Duration,
 	/// Overhead before generation starts (model load for Ollama, network TTFB for Claude).
 	pub overhead: std::time::Duration,
 	pub model: String,
 	pub thinking: ThinkingLevel,
+	/// Tools the model asked to call, see [Client::tools].
+	pub tool_calls: Vec<ToolCall> = Vec::new(),
+	/// `provider:model` of the backend that answered
+	pub backend: String = String::new(),
+	/// Backends of a [Client::fallback] chain that failed before this one answered, in order.
+	pub skipped: Vec<Skipped> = Vec::new(),
+	/// Served from [Client::disk_cache]; `cost_cents` is then 0.
+	pub cache_hit: bool = false,
+	/// Reasoning before the reply, when thinking is enabled and the provider returns it
+	pub thinking_blocks: Vec<ThinkingBlock> = Vec::new(),
+	/// Why generation ended, as the provider put it, eg `end_turn`, `max_tokens` or `stop_sequence`
+	pub stop_reason: Option<String> = None,
+	/// Which of the [stop sequences](Client::stop_sequences) ended generation
+	pub stop_sequence: Option<String> = None,
+}
+
+/// Incremental piece of a streamed reply, see [Client::conversation_stream].
+#[derive(Clone, Debug)]
+pub enum StreamEvent {
+	Text(String),
+	Thinking(String),
+	/// Usage reported so far. Each event supersedes the previous one.
+	Usage(Usage),
+	/// Emitted once the call's input is complete.
+	ToolUse(ToolCall),
+	/// Emitted once the block is complete; its text has already come through as [Thinking](StreamEvent::Thinking).
+	ThinkingBlock(ThinkingBlock),
+	/// Sent first by a [fallback](Client::fallback) chain
+	Backend {
+		/// `provider:model` of the backend answering
+		name: String,
+		/// Backends passed over before it, see [Response::skipped]
+		skipped: Vec<Skipped>,
+	},
+	/// Generation finished; no further text follows.
+	Stop {
+		reason: Option<String>,
+		/// Which of the [stop sequences](Client::stop_sequences) was hit, if that's the reason
+		stop_sequence: Option<String>,
+	},
+}
+
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+/// Token counts as reported by the provider.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+	/// Input tokens not read from or written to the prompt cache
+	pub input: u32,
+	/// Generated tokens, thinking included
+	pub output: u32,
+	pub cache_read: u32,
+	pub cache_write: u32,
+	/// Part of `output` spent on thinking, where the provider reports it separately
+	pub thinking: u32,
 }
 
+/// Serializes as `{"version": 1, "messages": [...]}`, see [CONVERSATION_FORMAT_VERSION].
 #[derive(Clone, Debug, Default)]
 pub struct Conversation(pub Vec<Message>);
 
-#[derive(Clone, Debug)]
+/// Bumped on breaking changes to the JSON shape of [Conversation]; new variants and optional fields don't count.
+pub const CONVERSATION_FORMAT_VERSION: u32 = 1;
+
+/// JSON: `{"role": "user", "type": "text", "data": "hi"}`, with `type` and `data` following [MessageContent].
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Message {
 	pub(crate) role: Role,
+	#[serde(flatten)]
 	pub(crate) content: MessageContent,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub(crate) cache: bool,
+	/// Reasoning that preceded an assistant turn, see [Conversation::add_response]
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub(crate) thinking: Vec<ThinkingBlock>,
 }
 
-#[derive(Clone, Debug)]
+/// Reasoning the model did before replying, see [Response::thinking_blocks].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingBlock {
+	Thinking {
+		text: String,
+		/// Claude's proof the block is unmodified; blocks without one aren't sent back to Claude
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		signature: Option<String>,
+	},
+	/// Flagged by Claude's safety systems; opaque, but must be passed back as is
+	Redacted { data: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ImageContent {
 	pub base64_data: String,
 	pub media_type: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ContentPart {
 	Text(String),
 	Image { base64_data: String, media_type: String },
 	Document { base64_data: String, media_type: String },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum MessageContent {
 	Text(String),
 	Image { base64_data: String, media_type: String },
 	TextAndImages { text: String, images: Vec<ImageContent> },
 	Document { base64_data: String, media_type: String },
 	Mixed { parts: Vec<ContentPart> },
+	ToolUse { text: String, calls: Vec<ToolCall> },
+	ToolResults(Vec<ToolResult>),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Role {
 	System,
 	User,
 	Assistant,
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, derive_more::FromStr)]
 pub enum ThinkingLevel {
 	#[default]
 	None,
@@ -308,6 +964,42 @@ pub enum Model {
 pub struct FileAttachment {
 	pub base64_data: String,
 	pub media_type: String,
+	/// Prompt cache breakpoint after this file, see [Client::cache_files].
+	pub cache: bool,
+}
+
+/// Function the model may call.
+///
+/// ```ignore
+/// let weather = Tool::new(
+/// 	"get_weather",
+/// 	"Current weather for a city",
+/// 	serde_json::json!({"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}),
+/// 	|input| async move { Ok(format!("Sunny in {}", input["city"])) },
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Tool {
+	pub name: String,
+	pub description: String,
+	/// JSON schema of the input object
+	pub input_schema: serde_json::Value,
+	handler: ToolHandler,
+}
+type ToolHandler = Arc<dyn Fn(serde_json::Value) -> Pin<Box<dyn Future<Output = eyre::Result<String>> + Send>> + Send + Sync>;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ToolCall {
+	pub id: String,
+	pub name: String,
+	pub input: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ToolResult {
+	pub tool_use_id: String,
+	pub content: String,
+	pub is_error: bool,
 }
 
 /// Client for interacting with LLMs.
@@ -322,26 +1014,110 @@ pub struct Client {
 	force_json: bool,
 	files: Vec<FileAttachment>,
 	thinking: ThinkingLevel,
+	tools: Vec<Tool>,
+	retry: RetryPolicy,
+	disk_cache: Option<DiskCache>,
+	spending: budget::Spending,
+	context_strategy: ContextStrategy,
+	auto_continue: usize,
+	prefill: Option<String>,
 }
-pub(crate) trait Backend: Send + Sync {
+/// A provider the [Client] sends requests to. Implement it to plug in your own, then use it with [Client::with_backend].
+///
+/// Retries, fallback, caching, budgets and context fitting happen in the [Client], so an implementation only translates a [Request].
+/// `duration`, `backend` and `cache_hit` of the returned [Response] are filled in by the client.
+///
+/// ```ignore
+/// struct Gateway;
+/// impl Backend for Gateway {
+/// 	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>> {
+/// 		Box::pin(async move {
+/// 			let text = call_gateway(request.conversation).await?;
+/// 			Ok(Response { text, model: "gateway".to_string(), ..Default::default() })
+/// 		})
+/// 	}
+/// 	fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>> { ... }
+/// 	fn name(&self) -> String {
+/// 		"gateway:default".to_string()
+/// 	}
+/// }
+/// let client = Client::with_backend(AppConfig::default(), Gateway);
+/// ```
+pub trait Backend: Send + Sync {
 	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>>;
+	/// Events as they arrive. [Usage](StreamEvent::Usage) should come before the stream ends, [Stop](StreamEvent::Stop) last.
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>>;
+	/// `provider:model`, for [Response::backend] and logs
+	fn name(&self) -> String;
+	/// What a reply with `usage` costs; for assembling a [Response] from a stream.
+	fn cost_cents(&self, _usage: &Usage) -> f32 {
+		0.0
+	}
+	/// What a streamed reply with `usage` costs when `backend` answered it, as named by [StreamEvent::Backend] or else [name](Self::name).
+	/// Only a backend that passes requests on to others needs to tell them apart.
+	fn stream_cost_cents(&self, _backend: &str, usage: &Usage) -> f32 {
+		self.cost_cents(usage)
+	}
+	/// Input tokens of `request` as the provider counts them; a local estimate unless the provider can count.
+	fn count_tokens<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<u32>> + Send + 'a>> {
+		Box::pin(async move { Ok(request.estimate_tokens()) })
+	}
+	/// Most output tokens `request` may produce: its `max_tokens`, capped by the model, or the model's ceiling. `None` if unknown.
+	fn max_output(&self, request: &Request<'_>) -> Option<u32> {
+		request.max_tokens.map(|max_tokens| max_tokens as u32)
+	}
+	/// Most input tokens `request` may have: the context window less the output it reserves. `None` if unknown.
+	fn input_limit(&self, _request: &Request<'_>) -> Option<u32> {
+		None
+	}
+	/// Whether a conversation ending in an assistant message is continued from where that message stops, rather than answered anew.
+	/// The [Client] emulates it otherwise.
+	fn native_prefill(&self, _request: &Request<'_>) -> bool {
+		false
+	}
+}
+/// Missing keys surface as [Error::MissingCredentials] once a request is made, so that building a [Client] never fails.
+fn claude_api_key(config: &config::AppConfig) -> Option<String> {
+	config.claude_token.clone().or_else(|| std::env::var("CLAUDE_TOKEN").ok())
 }
-fn claude_api_key(config: &config::AppConfig) -> String {
-	config
-		.claude_token
-		.clone()
-		.or_else(|| std::env::var("CLAUDE_TOKEN").ok())
-		.expect("CLAUDE_TOKEN not set in config or environment")
+fn gemini_api_key(config: &config::AppConfig) -> Option<String> {
+	config.gemini_token.clone().or_else(|| std::env::var("GEMINI_API_KEY").ok())
 }
 
-pub(crate) struct Request<'a> {
+/// Everything a [Backend] needs to make one call, assembled by the [Client] from its settings.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Request<'a> {
 	pub conversation: &'a Conversation,
 	pub temperature: Option<f32>,
 	pub max_tokens: Option<usize>,
 	pub stop_sequences: Option<Vec<&'a str>>,
 	pub force_json: bool,
+	/// Reply must match this schema. Set by [Client::conversation_typed].
+	pub json_schema: Option<&'a serde_json::Value>,
 	pub files: &'a [FileAttachment],
 	pub thinking: ThinkingLevel,
+	pub tools: &'a [Tool],
+}
+impl<'a> Request<'a> {
+	fn with_conversation<'b>(&'b self, conversation: &'b Conversation) -> Request<'b>
+	where
+		'a: 'b,
+	{
+		Request {
+			conversation,
+			stop_sequences: self.stop_sequences.clone(),
+			..*self
+		}
+	}
+
+	/// Rough input token count: about 4 bytes of JSON per token, attachments counted by their decoded size.
+	pub fn estimate_tokens(&self) -> u32 {
+		let conversation = serde_json::to_string(self.conversation).map_or(0, |s| s.len());
+		let files: usize = self.files.iter().map(|f| f.base64_data.len() * 3 / 4).sum();
+		let tools: usize = self.tools.iter().map(|t| t.name.len() + t.description.len() + t.input_schema.to_string().len()).sum();
+		((conversation + files + tools) / 4) as u32
+	}
 }
 
 impl std::fmt::Debug for Client {
@@ -353,6 +1129,42 @@ impl std::fmt::Debug for Client {
 			.field("force_json", &self.force_json)
 			.field("thinking", &self.thinking)
 			.field("files", &self.files)
+			.field("tools", &self.tools)
+			.field("retry", &self.retry)
+			.field("disk_cache", &self.disk_cache)
+			.field("spending", &self.spending)
+			.field("context_strategy", &self.context_strategy)
+			.field("auto_continue", &self.auto_continue)
+			.field("prefill", &self.prefill)
+			.finish_non_exhaustive()
+	}
+}
+
+impl Tool {
+	pub fn new<F, Fut>(name: impl Into<String>, description: impl Into<String>, input_schema: serde_json::Value, handler: F) -> Self
+	where
+		F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = eyre::Result<String>> + Send + 'static,
+	{
+		Self {
+			name: name.into(),
+			description: description.into(),
+			input_schema,
+			handler: Arc::new(move |input| Box::pin(handler(input))),
+		}
+	}
+
+	pub async fn call(&self, input: serde_json::Value) -> eyre::Result<String> {
+		(self.handler)(input).await
+	}
+}
+
+impl std::fmt::Debug for Tool {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Tool")
+			.field("name", &self.name)
+			.field("description", &self.description)
+			.field("input_schema", &self.input_schema)
 			.finish_non_exhaustive()
 	}
 }
@@ -379,6 +1191,16 @@ impl Default for Client {
 	}
 }
 
+impl std::ops::AddAssign for Usage {
+	fn add_assign(&mut self, other: Self) {
+		self.input += other.input;
+		self.output += other.output;
+		self.cache_read += other.cache_read;
+		self.cache_write += other.cache_write;
+		self.thinking += other.thinking;
+	}
+}
+
 impl std::fmt::Display for ThinkingLevel {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
@@ -397,10 +1219,112 @@ impl std::fmt::Display for Response {
 		let gen_secs = secs - overhead;
 		let chars = self.text.len();
 		let ms_per_char = if chars > 0 { gen_secs * 1000.0 / chars as f32 } else { 0.0 };
+		let cache = match self.usage.cache_read + self.usage.cache_write {
+			0 => String::new(),
+			_ => format!(" ({} cache read, {} cache write)", self.usage.cache_read, self.usage.cache_write),
+		};
+		let hit = match self.cache_hit {
+			true => " (cache hit)",
+			false => "",
+		};
 		write!(
 			f,
-			"[model: {} | thinking: {} | cost: {:.4}¢ | overhead: {overhead:.1}s | gen: {gen_secs:.1}s | {ms_per_char:.1}ms/char]",
-			self.model, self.thinking, self.cost_cents
+			"[model: {}{hit} | thinking: {} | tokens: {} in{cache} / {} out | cost: {:.4}¢ | overhead: {overhead:.1}s | gen: {gen_secs:.1}s | {ms_per_char:.1}ms/char]",
+			self.model,
+			self.thinking,
+			self.usage.input + self.usage.cache_read + self.usage.cache_write,
+			self.usage.output,
+			self.cost_cents
 		)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn conversation_json_roundtrip() {
+		let mut conv = Conversation::new_with_system("be brief");
+		conv.add(Role::User, "weather in Paris?");
+		conv.0.push(Message::new_tool_use(
+			String::new(),
+			vec![ToolCall {
+				id: "call_1".to_string(),
+				name: "get_weather".to_string(),
+				input: serde_json::json!({"city": "Paris"}),
+			}],
+		));
+
+		let json = serde_json::to_value(&conv).unwrap();
+		assert_eq!(json["version"], 1);
+		assert_eq!(json["messages"][1], serde_json::json!({"role": "user", "type": "text", "data": "weather in Paris?"}));
+		assert_eq!(json["messages"][2]["data"]["calls"][0]["name"], "get_weather");
+
+		let back: Conversation = serde_json::from_value(json).unwrap();
+		assert!(matches!(&back.0[2].content, MessageContent::ToolUse { calls, .. } if calls[0].input["city"] == "Paris"));
+		assert!(serde_json::from_str::<Conversation>(r#"{"version": 2, "messages": []}"#).is_err());
+	}
+
+	fn adder() -> Tool {
+		Tool::new("add", "Adds a and b", serde_json::json!({"type": "object"}), |input| async move {
+			Ok((input["a"].as_i64().unwrap() + input["b"].as_i64().unwrap()).to_string())
+		})
+	}
+
+	#[tokio::test]
+	async fn tool_loop() {
+		let mock = testing::MockBackend::new()
+			.tool_call("add", serde_json::json!({"a": 2, "b": 3}))
+			.tool_call("subtract", serde_json::json!({}))
+			.reply("5");
+		let requests = mock.requests();
+		let client = Client::with_backend(config::AppConfig::default(), mock).tools(vec![adder()]);
+
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "2+3?");
+		let response = client.conversation_with_tools(&mut conv).await.unwrap();
+		assert_eq!(response.text, "5");
+		assert_eq!(requests.len(), 3);
+		assert_eq!(conv.0.len(), 5, "tool calls and results are kept, the final reply is not");
+		assert!(matches!(&conv.0[2].content, MessageContent::ToolResults(r) if r[0].tool_use_id == "mock_call_0" && r[0].content == "5" && !r[0].is_error));
+		assert!(matches!(&conv.0[4].content, MessageContent::ToolResults(r) if r[0].content == "Unknown tool: subtract" && r[0].is_error));
+		assert_eq!(requests.last().unwrap().tools, ["add"]);
+	}
+
+	#[tokio::test]
+	async fn tool_loop_gives_up() {
+		let mock = (0..32).fold(testing::MockBackend::new(), |mock, _| mock.tool_call("add", serde_json::json!({"a": 1, "b": 1})));
+		let requests = mock.requests();
+		let client = Client::with_backend(config::AppConfig::default(), mock).tools(vec![adder()]);
+		assert!(matches!(client.ask_with_tools("count up").await, Err(Error::InvalidResponse(_))));
+		assert_eq!(requests.len(), 32);
+	}
+
+	#[derive(Debug, Deserialize, JsonSchema)]
+	struct Answer {
+		value: i64,
+	}
+
+	#[tokio::test]
+	async fn typed_reply_retries_invalid_json() {
+		let mock = testing::MockBackend::new().reply("{\"value\": \"six\"}").reply("{\"value\": 6}");
+		let requests = mock.requests();
+		let client = Client::with_backend(config::AppConfig::default(), mock);
+		assert_eq!(client.ask_typed::<Answer>("2*3?").await.unwrap().value, 6);
+
+		let retried = requests.last().unwrap();
+		assert!(retried.json_schema.unwrap()["properties"]["value"].is_object());
+		assert_eq!(retried.conversation.0.len(), 3);
+		assert!(matches!(&retried.conversation.0[2].content, MessageContent::Text(t) if t.starts_with("That response failed validation")));
+	}
+
+	#[tokio::test]
+	async fn typed_reply_gives_up() {
+		let mock = testing::MockBackend::new().reply("six").reply("still six").reply("6!");
+		let requests = mock.requests();
+		let client = Client::with_backend(config::AppConfig::default(), mock);
+		assert!(matches!(client.ask_typed::<Answer>("2*3?").await, Err(Error::InvalidResponse(_))));
+		assert_eq!(requests.len(), 3);
+	}
+}
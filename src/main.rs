@@ -1,21 +1,50 @@
+use std::path::PathBuf;
+
 use ask_llm::{
-	Client, Model,
+	Client, Conversation, DiskCache, Ledger, Model, Role,
 	config::{AppConfig, SettingsFlags},
 };
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+mod chat;
 
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
-	question: String,
-	#[clap(short, long, default_value = "medium")]
+	#[command(subcommand)]
+	command: Option<Command>,
+	#[clap(required = true)]
+	question: Option<String>,
+	#[clap(short, long, default_value = "medium", global = true)]
 	model: Model,
 	/// If true, will avoid streaming (caps response at 4096 tokens)
 	#[clap(short, long)]
 	fast: bool,
+	/// Conversation json file to continue. The question and the reply are appended and written back; created if missing.
+	#[clap(long, global = true)]
+	conversation: Option<PathBuf>,
+	/// Reuse replies to identical requests from the on-disk cache
+	#[clap(long, global = true)]
+	cache: bool,
+	/// With --cache: ask anyway, overwriting the cached reply
+	#[clap(long, global = true, requires = "cache")]
+	bypass_cache: bool,
 	#[command(flatten)]
 	settings: SettingsFlags,
 }
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Interactive chat, streaming replies. Starts from `--conversation` if given.
+	Chat,
+	/// Delete all replies stored by --cache
+	ClearCache,
+	/// Spend recorded in the ledger, by day and model
+	Cost {
+		/// How many days back to include, today included
+		#[clap(long, default_value = "30")]
+		days: u32,
+	},
+}
 #[tokio::main]
 async fn main() {
 	v_utils::clientside!();
@@ -23,11 +52,80 @@ async fn main() {
 
 	let config = AppConfig::try_build(cli.settings).expect("Failed to build config");
 
-	let mut client = Client::new(config).model(cli.model);
+	let mut client = Client::new(config).model(cli.model).ledger(Ledger::default());
 	if cli.fast {
 		client = client.max_tokens(4096);
 	}
-	let answer: String = client.ask(cli.question).await.unwrap().text;
+	if cli.cache {
+		client = client.disk_cache(DiskCache {
+			bypass: cli.bypass_cache,
+			..Default::default()
+		});
+	}
+
+	match cli.command {
+		Some(Command::Chat) => return chat::run(client, cli.conversation).await,
+		Some(Command::ClearCache) => return DiskCache::default().clear().expect("Failed to clear cache"),
+		Some(Command::Cost { days }) => return print_cost(days),
+		None => {}
+	}
+	let question = cli.question.expect("required by clap");
+
+	let Some(path) = cli.conversation else {
+		let answer: String = client.ask(question).await.unwrap().text;
+		println!("{answer:#}");
+		return;
+	};
+	let mut conv = match path.exists() {
+		true => chat::load(&path).unwrap_or_else(|e| panic!("{e}")),
+		false => Conversation::new(),
+	};
+	conv.add(Role::User, question);
+	let response = client.conversation(&conv).await.unwrap();
+	println!("{:#}", response.text);
+	conv.add_response(&response);
+	chat::save(&conv, &path).unwrap_or_else(|e| panic!("{e}"));
+}
 
-	println!("{answer:#}");
+fn print_cost(days: u32) {
+	use std::collections::BTreeMap;
+
+	#[derive(Default)]
+	struct Row {
+		calls: usize,
+		input: u64,
+		output: u64,
+		cents: f32,
+	}
+
+	let entries = Ledger::default().entries().expect("Failed to read ledger");
+	let tz = jiff::tz::TimeZone::system();
+	let since = jiff::Zoned::now().date().saturating_sub(jiff::Span::new().days(days.saturating_sub(1)));
+	let mut by_day: BTreeMap<jiff::civil::Date, BTreeMap<String, Row>> = BTreeMap::new();
+	for entry in entries {
+		let day = entry.timestamp.to_zoned(tz.clone()).date();
+		if day < since {
+			continue;
+		}
+		let row = by_day.entry(day).or_default().entry(entry.model).or_default();
+		row.calls += 1;
+		row.input += (entry.usage.input + entry.usage.cache_read + entry.usage.cache_write) as u64;
+		row.output += entry.usage.output as u64;
+		row.cents += entry.cost_cents;
+	}
+	if by_day.is_empty() {
+		println!("Nothing spent in the last {days} days");
+		return;
+	}
+
+	let mut total = 0.0;
+	for (day, models) in &by_day {
+		let day_total: f32 = models.values().map(|row| row.cents).sum();
+		total += day_total;
+		println!("{day}  {day_total:.4}¢");
+		for (model, row) in models {
+			println!("  {model:<40} {:>5} calls  {:>10} in / {:>8} out  {:.4}¢", row.calls, row.input, row.output, row.cents);
+		}
+	}
+	println!("Total over the last {days} days: {total:.4}¢");
 }
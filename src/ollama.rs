@@ -1,7 +1,7 @@
-use eyre::{Result, bail};
+use futures::stream::StreamExt as _;
 use serde::{Deserialize, Serialize};
 
-use crate::{Backend, Request, Response, Role, ThinkingLevel};
+use crate::{Backend, ContentPart, Error, MessageContent, Request, Response, ResponseStream, Result, Role, StreamEvent, ThinkingBlock, ThinkingLevel, ToolCall, Usage};
 
 pub(crate) struct Ollama {
 	pub model: String,
@@ -9,11 +9,66 @@ pub(crate) struct Ollama {
 }
 impl Ollama {
 	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
-		if !request.files.is_empty() {
-			bail!("Ollama backend does not support file attachments");
+		let ollama_request = self.build_request(request, false)?;
+		let response = self.send(&ollama_request).await?;
+
+		let value: serde_json::Value = response.json().await?;
+		tracing::debug!(?value);
+
+		let parsed: OllamaResponse = serde_json::from_value(value.clone()).inspect_err(|e| {
+			eprintln!(
+				"Failed to parse Ollama response: {}\n{e:?}",
+				serde_json::to_string_pretty(&value).unwrap_or_else(|_| format!("{:?}", value))
+			);
+		})?;
+
+		let overhead_nanos = parsed.load_duration + parsed.prompt_eval_duration;
+		let tool_calls = parsed.message.tool_calls.into_iter().enumerate().map(|(i, c)| c.into_tool_call(i)).collect();
+		let thinking_blocks = parsed
+			.message
+			.thinking
+			.filter(|t| !t.is_empty())
+			.map(|text| ThinkingBlock::Thinking { text, signature: None })
+			.into_iter()
+			.collect();
+		Ok(Response {
+			text: parsed.message.content,
+			cost_cents: 0.0,
+			usage: Usage {
+				input: parsed.prompt_eval_count,
+				output: parsed.eval_count,
+				..Default::default()
+			},
+			duration: std::time::Duration::ZERO,
+			overhead: std::time::Duration::from_nanos(overhead_nanos),
+			model: self.model.clone(),
+			thinking: request.thinking,
+			tool_calls,
+			thinking_blocks,
+			stop_reason: parsed.done_reason,
+			..
+		})
+	}
+
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		let ollama_request = self.build_request(request, true)?;
+		let response = self.send(&ollama_request).await?;
+		Ok(ndjson_events(response.bytes_stream()))
+	}
+
+	async fn send(&self, ollama_request: &OllamaRequest) -> Result<reqwest::Response> {
+		let response = reqwest::Client::new().post(&self.url).json(ollama_request).send().await?;
+
+		match response.status().is_success() {
+			true => Ok(response),
+			false => Err(Error::from_response(response).await),
 		}
+	}
 
+	fn build_request(&self, request: &Request<'_>, stream: bool) -> Result<OllamaRequest> {
 		let mut messages: Vec<OllamaMessage> = Vec::new();
+		// Tool results are matched to their call by function name, not id
+		let mut tool_names: std::collections::HashMap<&str, &str> = std::collections::HashMap::new();
 
 		for message in &request.conversation.0 {
 			let role = match message.role {
@@ -21,22 +76,88 @@ impl Ollama {
 				Role::User => "user",
 				Role::Assistant => "assistant",
 			};
-			let text = match &message.content {
-				crate::MessageContent::Text(t) => t.clone(),
-				_ => bail!("Ollama backend only supports text messages"),
-			};
-			messages.push(OllamaMessage {
-				role: role.to_string(),
-				content: text,
-			});
+			match &message.content {
+				MessageContent::Text(t) => messages.push(OllamaMessage::new(role, t.clone())),
+				MessageContent::Image { base64_data, media_type } | MessageContent::Document { base64_data, media_type } => {
+					let mut msg = OllamaMessage::new(role, String::new());
+					msg.attach(media_type, base64_data)?;
+					messages.push(msg);
+				}
+				MessageContent::TextAndImages { text, images } => {
+					let mut msg = OllamaMessage::new(role, text.clone());
+					for image in images {
+						msg.attach(&image.media_type, &image.base64_data)?;
+					}
+					messages.push(msg);
+				}
+				MessageContent::Mixed { parts } => {
+					let mut msg = OllamaMessage::new(role, String::new());
+					for part in parts {
+						match part {
+							ContentPart::Text(text) => msg.push_text(text),
+							ContentPart::Image { base64_data, media_type } | ContentPart::Document { base64_data, media_type } => msg.attach(media_type, base64_data)?,
+						}
+					}
+					messages.push(msg);
+				}
+				MessageContent::ToolUse { text, calls } => {
+					let mut msg = OllamaMessage::new(role, text.clone());
+					for call in calls {
+						tool_names.insert(&call.id, &call.name);
+						msg.tool_calls.push(OllamaToolCall {
+							id: Some(call.id.clone()),
+							function: OllamaFunctionCall {
+								name: call.name.clone(),
+								arguments: call.input.clone(),
+							},
+						});
+					}
+					messages.push(msg);
+				}
+				MessageContent::ToolResults(results) => {
+					for result in results {
+						let mut msg = OllamaMessage::new("tool", result.content.clone());
+						msg.tool_name = tool_names.get(result.tool_use_id.as_str()).map(|name| name.to_string());
+						messages.push(msg);
+					}
+				}
+			}
+			if matches!(message.role, Role::Assistant)
+				&& !message.thinking.is_empty()
+				&& let Some(msg) = messages.last_mut()
+			{
+				// redacted blocks are only meaningful to Claude
+				let text: Vec<&str> = message
+					.thinking
+					.iter()
+					.filter_map(|block| match block {
+						ThinkingBlock::Thinking { text, .. } => Some(text.as_str()),
+						ThinkingBlock::Redacted { .. } => None,
+					})
+					.collect();
+				msg.thinking = Some(text.join("\n\n")).filter(|t| !t.is_empty());
+			}
 		}
 
-		if request.force_json {
-			if let Some(last) = messages.last_mut() {
-				if last.role == "user" {
-					last.content.push_str("\n\nRespond with valid JSON only, no other text.");
-				}
+		// Prepend files to the first user message
+		if !request.files.is_empty()
+			&& let Some(first_user_msg) = messages.iter_mut().find(|m| m.role == "user")
+		{
+			let mut msg = OllamaMessage::new("user", String::new());
+			for file in request.files {
+				msg.attach(&file.media_type, &file.base64_data)?;
 			}
+			msg.push_text(&first_user_msg.content);
+			msg.images.append(&mut first_user_msg.images);
+			first_user_msg.content = msg.content;
+			first_user_msg.images = msg.images;
+		}
+
+		if request.force_json
+			&& let Some(last) = messages.last_mut()
+			&& last.role == "user"
+		{
+			last.content.push_str("\n\nRespond with valid JSON only, no other text.");
 		}
 
 		let think = !matches!(request.thinking, ThinkingLevel::None);
@@ -44,46 +165,26 @@ impl Ollama {
 		let mut ollama_request = OllamaRequest {
 			model: self.model.clone(),
 			messages,
-			stream: false,
+			stream,
 			think,
 			options: OllamaOptions {
 				temperature: request.temperature.unwrap_or(0.0),
 				num_predict: request.max_tokens,
 				stop: None,
 			},
+			tools: request
+				.tools
+				.iter()
+				.map(|t| serde_json::json!({"type": "function", "function": {"name": t.name, "description": t.description, "parameters": t.input_schema}}))
+				.collect(),
+			format: request.json_schema.cloned(),
 		};
 
 		if let Some(ref seqs) = request.stop_sequences {
 			ollama_request.options.stop = Some(seqs.iter().map(|s| s.to_string()).collect());
 		}
 
-		let response = reqwest::Client::new().post(&self.url).json(&ollama_request).send().await?;
-
-		let status = response.status();
-		if !status.is_success() {
-			let body = response.text().await.unwrap_or_default();
-			bail!("Ollama request failed ({status}): {body}");
-		}
-
-		let value: serde_json::Value = response.json().await?;
-		tracing::debug!(?value);
-
-		let parsed: OllamaResponse = serde_json::from_value(value.clone()).inspect_err(|e| {
-			eprintln!(
-				"Failed to parse Ollama response: {}\n{e:?}",
-				serde_json::to_string_pretty(&value).unwrap_or_else(|_| format!("{:?}", value))
-			);
-		})?;
-
-		let overhead_nanos = parsed.load_duration + parsed.prompt_eval_duration;
-		Ok(Response {
-			text: parsed.message.content,
-			cost_cents: 0.0,
-			duration: std::time::Duration::ZERO,
-			overhead: std::time::Duration::from_nanos(overhead_nanos),
-			model: self.model.clone(),
-			thinking: request.thinking,
-		})
+		Ok(ollama_request)
 	}
 }
 
@@ -91,6 +192,97 @@ impl Backend for Ollama {
 	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
 		Box::pin(self.do_conversation(request))
 	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		format!("ollama:{}", self.model)
+	}
+}
+
+/// Turn the body of a streaming chat response (one JSON object per line) into [StreamEvent]s.
+fn ndjson_events(bytes: impl futures::Stream<Item = reqwest::Result<bytes::Bytes>> + Send + 'static) -> ResponseStream {
+	struct State {
+		bytes: futures::stream::BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+		buffer: Vec<u8>,
+		pending: std::collections::VecDeque<StreamEvent>,
+		tool_calls_seen: usize,
+		thinking: String,
+	}
+	impl State {
+		fn push_line(&mut self, line: &[u8]) -> Result<()> {
+			if line.iter().all(u8::is_ascii_whitespace) {
+				return Ok(());
+			}
+			let chunk: OllamaStreamChunk = serde_json::from_slice(line)?;
+			if let Some(thinking) = chunk.message.thinking.filter(|t| !t.is_empty()) {
+				self.thinking.push_str(&thinking);
+				self.pending.push_back(StreamEvent::Thinking(thinking));
+			}
+			if !chunk.message.content.is_empty() {
+				self.pending.push_back(StreamEvent::Text(chunk.message.content));
+			}
+			for call in chunk.message.tool_calls {
+				self.pending.push_back(StreamEvent::ToolUse(call.into_tool_call(self.tool_calls_seen)));
+				self.tool_calls_seen += 1;
+			}
+			if chunk.done {
+				if !self.thinking.is_empty() {
+					self.pending.push_back(StreamEvent::ThinkingBlock(ThinkingBlock::Thinking {
+						text: std::mem::take(&mut self.thinking),
+						signature: None,
+					}));
+				}
+				self.pending.push_back(StreamEvent::Usage(Usage {
+					input: chunk.prompt_eval_count,
+					output: chunk.eval_count,
+					..Default::default()
+				}));
+				self.pending.push_back(StreamEvent::Stop {
+					reason: chunk.done_reason,
+					stop_sequence: None,
+				});
+			}
+			Ok(())
+		}
+	}
+
+	let state = State {
+		bytes: bytes.boxed(),
+		buffer: Vec::new(),
+		pending: Default::default(),
+		tool_calls_seen: 0,
+		thinking: String::new(),
+	};
+	Box::pin(futures::stream::unfold(state, |mut state| async move {
+		loop {
+			if let Some(event) = state.pending.pop_front() {
+				return Some((Ok(event), state));
+			}
+			match state.bytes.next().await {
+				Some(Ok(chunk)) => {
+					state.buffer.extend_from_slice(&chunk);
+					while let Some(end) = state.buffer.iter().position(|&b| b == b'\n') {
+						let line: Vec<u8> = state.buffer.drain(..=end).collect();
+						if let Err(e) = state.push_line(&line) {
+							return Some((Err(e), state));
+						}
+					}
+				}
+				Some(Err(e)) => return Some((Err(e.into()), state)),
+				// Last line is not required to be newline-terminated
+				None if !state.buffer.is_empty() => {
+					let line = std::mem::take(&mut state.buffer);
+					if let Err(e) = state.push_line(&line) {
+						return Some((Err(e), state));
+					}
+				}
+				None => return None,
+			}
+		}
+	}))
 }
 
 #[derive(Debug, Serialize)]
@@ -100,6 +292,11 @@ struct OllamaRequest {
 	stream: bool,
 	think: bool,
 	options: OllamaOptions,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	tools: Vec<serde_json::Value>,
+	/// JSON schema the reply must follow
+	#[serde(skip_serializing_if = "Option::is_none")]
+	format: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -115,6 +312,88 @@ struct OllamaOptions {
 struct OllamaMessage {
 	role: String,
 	content: String,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	tool_calls: Vec<OllamaToolCall>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	tool_name: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	thinking: Option<String>,
+	/// Base64, for vision models
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	images: Vec<String>,
+}
+impl OllamaMessage {
+	fn new(role: &str, content: String) -> Self {
+		Self {
+			role: role.to_string(),
+			content,
+			tool_calls: Vec::new(),
+			tool_name: None,
+			thinking: None,
+			images: Vec::new(),
+		}
+	}
+
+	fn push_text(&mut self, text: &str) {
+		if !self.content.is_empty() && !text.is_empty() {
+			self.content.push_str("\n\n");
+		}
+		self.content.push_str(text);
+	}
+
+	/// Images go into `images`; text-based files are decoded and inlined, same as the Claude backend. Ollama reads nothing else, eg PDFs.
+	fn attach(&mut self, media_type: &str, base64_data: &str) -> Result<()> {
+		use base64::Engine;
+		if media_type.starts_with("image/") {
+			self.images.push(base64_data.to_string());
+			return Ok(());
+		}
+		let unsupported = || Error::UnsupportedContent {
+			backend: "Ollama",
+			detail: format!("{media_type} attachments"),
+		};
+		if !is_text(media_type) {
+			return Err(unsupported());
+		}
+		let bytes = base64::engine::general_purpose::STANDARD.decode(base64_data).map_err(|_| unsupported())?;
+		self.push_text(&String::from_utf8_lossy(&bytes));
+		Ok(())
+	}
+}
+
+/// Media types that are plain text, so can be inlined into the prompt.
+fn is_text(media_type: &str) -> bool {
+	let essence = media_type.split(';').next().unwrap_or_default().trim();
+	essence.starts_with("text/")
+		|| essence.ends_with("+json")
+		|| essence.ends_with("+xml")
+		|| matches!(
+			essence,
+			"application/json" | "application/xml" | "application/yaml" | "application/x-yaml" | "application/toml" | "application/javascript" | "application/x-sh" | "application/sql"
+		)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OllamaToolCall {
+	/// Only set by recent Ollama versions
+	#[serde(skip_serializing_if = "Option::is_none")]
+	id: Option<String>,
+	function: OllamaFunctionCall,
+}
+impl OllamaToolCall {
+	fn into_tool_call(self, index: usize) -> ToolCall {
+		ToolCall {
+			id: self.id.unwrap_or_else(|| format!("call_{index}")),
+			name: self.function.name,
+			input: self.function.arguments,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OllamaFunctionCall {
+	name: String,
+	arguments: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -124,4 +403,101 @@ struct OllamaResponse {
 	load_duration: u64,
 	#[serde(default)]
 	prompt_eval_duration: u64,
+	#[serde(default)]
+	prompt_eval_count: u32,
+	#[serde(default)]
+	eval_count: u32,
+	done_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaStreamChunk {
+	message: OllamaChunkMessage,
+	#[serde(default)]
+	done: bool,
+	done_reason: Option<String>,
+	#[serde(default)]
+	prompt_eval_count: u32,
+	#[serde(default)]
+	eval_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChunkMessage {
+	#[serde(default)]
+	content: String,
+	thinking: Option<String>,
+	#[serde(default)]
+	tool_calls: Vec<OllamaToolCall>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Conversation, FileAttachment, ImageContent, Message, testing};
+
+	#[test]
+	fn images_and_files() {
+		let ollama = Ollama {
+			model: "llava".to_string(),
+			url: "http://localhost/api/chat".to_string(),
+		};
+		let mut conv = Conversation::new();
+		conv.0.push(Message::new_with_text_and_images(
+			Role::User,
+			"what's this?".to_string(),
+			vec![ImageContent {
+				base64_data: "aW1n".to_string(),
+				media_type: "image/png".to_string(),
+			}],
+		));
+		let file = |media_type: &str, base64_data: &str| FileAttachment {
+			base64_data: base64_data.to_string(),
+			media_type: media_type.to_string(),
+			cache: false,
+		};
+		let files = [file("text/plain", "bm90ZXM="), file("image/jpeg", "cGhvdG8=")];
+		let mut request = Request {
+			files: &files,
+			..testing::request(&conv)
+		};
+		let payload = serde_json::to_value(ollama.build_request(&request, false).unwrap()).unwrap();
+		assert_eq!(
+			payload["messages"][0],
+			serde_json::json!({"role": "user", "content": "notes\n\nwhat's this?", "images": ["cGhvdG8=", "aW1n"]})
+		);
+
+		// ASCII-only, so valid UTF-8, but still not text
+		let pdf = [file("application/pdf", "JVBERi0xLjcKJSVFT0YK")];
+		request.files = &pdf;
+		assert!(matches!(ollama.build_request(&request, false), Err(Error::UnsupportedContent { .. })));
+		let json = [file("application/json; charset=utf-8", "eyJhIjoxfQ==")];
+		request.files = &json;
+		let payload = serde_json::to_value(ollama.build_request(&request, false).unwrap()).unwrap();
+		assert_eq!(payload["messages"][0]["content"], "{\"a\":1}\n\nwhat's this?");
+	}
+
+	#[tokio::test]
+	async fn lines_split_across_chunks() {
+		let chunks = [
+			"{\"message\":{\"content\":\"\",\"thinking\":\"Hm\"},\"done\":false}\n{\"message\":{\"con",
+			"tent\":\"Hel\"},\"done\":false}\n",
+			"{\"message\":{\"content\":\"lo\"},\"done\":false}",
+			"\n{\"message\":{\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":12,\"eval_count\":3}",
+		];
+		let bytes = futures::stream::iter(chunks.map(|c| Ok(bytes::Bytes::from_static(c.as_bytes()))));
+		let events: Vec<_> = ndjson_events(bytes).map(Result::unwrap).collect().await;
+
+		let text: String = events
+			.iter()
+			.filter_map(|e| match e {
+				StreamEvent::Text(t) => Some(t.as_str()),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(text, "Hello");
+		assert!(matches!(&events[events.len() - 3], StreamEvent::ThinkingBlock(ThinkingBlock::Thinking { text, .. }) if text == "Hm"));
+		assert!(matches!(events[events.len() - 2], StreamEvent::Usage(Usage { input: 12, output: 3, .. })));
+		assert!(matches!(&events[events.len() - 1], StreamEvent::Stop { reason: Some(r), .. } if r == "stop"));
+	}
 }
@@ -0,0 +1,512 @@
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{Backend, ContentPart, Error, FileAttachment, MessageContent, Request, Response, ResponseStream, Result, Role, StreamEvent, ThinkingLevel, Usage};
+
+/// Any server speaking the OpenAI Chat Completions protocol: OpenAI itself, OpenRouter, Groq, vLLM, llama.cpp server, LM Studio, etc.
+pub(crate) struct OpenAi {
+	/// Everything before `/chat/completions`, eg `https://api.openai.com/v1`
+	pub base_url: String,
+	/// Local servers usually don't need one
+	pub api_key: Option<String>,
+	pub model: String,
+}
+impl OpenAi {
+	///docs: https://platform.openai.com/docs/api-reference/chat/create
+	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
+		let ttfb_start = std::time::Instant::now();
+		let response = self.send(request, false).await?;
+		let ttfb = ttfb_start.elapsed();
+
+		let value: Value = response.json().await?;
+		tracing::debug!(?value);
+		let parsed: ChatCompletion = serde_json::from_value(value.clone()).inspect_err(|e| tracing::error!(%value, "Failed to parse OpenAI-compatible response: {e}"))?;
+
+		let Some(choice) = parsed.choices.into_iter().next() else {
+			return Err(Error::InvalidResponse("OpenAI-compatible response contained no choices".to_string()));
+		};
+		if choice.finish_reason.as_deref() == Some("content_filter") {
+			return Err(Error::Refusal("content_filter".to_string()));
+		}
+
+		Ok(Response {
+			text: choice.message.content.unwrap_or_default(),
+			cost_cents: 0.0,
+			usage: parsed.usage.map(Usage::from).unwrap_or_default(),
+			duration: std::time::Duration::ZERO,
+			overhead: ttfb,
+			model: self.model.clone(),
+			thinking: request.thinking,
+			stop_reason: choice.finish_reason,
+			..
+		})
+	}
+
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		let response = self.send(request, true).await?;
+		Ok(sse_events(response))
+	}
+
+	async fn send(&self, request: &Request<'_>, stream: bool) -> Result<reqwest::Response> {
+		let payload = self.payload(request, stream)?;
+		tracing::debug!(?payload);
+
+		let mut headers = HeaderMap::new();
+		headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+		if let Some(api_key) = &self.api_key {
+			headers.insert(
+				AUTHORIZATION,
+				HeaderValue::from_str(&format!("Bearer {api_key}")).map_err(|_| Error::Config("API key is not a valid header value".to_string()))?,
+			);
+		}
+
+		let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+		let response = reqwest::Client::new().post(url).headers(headers).json(&payload).send().await?;
+
+		match response.status().is_success() {
+			true => Ok(response),
+			false => Err(Error::from_response(response).await),
+		}
+	}
+
+	fn payload(&self, request: &Request<'_>, stream: bool) -> Result<Value> {
+		if !request.tools.is_empty() {
+			return Err(unsupported_tools());
+		}
+
+		let mut messages: Vec<OpenAiMessage> = request.conversation.0.iter().map(|m| OpenAiMessage::new(m.role, &m.content)).collect::<Result<_>>()?;
+
+		// Prepend files to the first user message
+		if !request.files.is_empty()
+			&& let Some(first_user_msg) = messages.iter_mut().find(|m| m.role == "user")
+		{
+			let mut parts: Vec<OpenAiPart> = request.files.iter().map(file_to_part).collect();
+			match std::mem::replace(&mut first_user_msg.content, OpenAiContent::Parts(Vec::new())) {
+				OpenAiContent::Text(text) => parts.push(OpenAiPart::Text { text }),
+				OpenAiContent::Parts(existing) => parts.extend(existing),
+			}
+			first_user_msg.content = OpenAiContent::Parts(parts);
+		}
+
+		if request.force_json {
+			// `json_object` mode is rejected unless the word "JSON" appears in the messages
+			if let Some(last) = messages.last_mut()
+				&& last.role == "user"
+			{
+				last.push_text("\n\nRespond with valid JSON only, no other text.");
+			}
+		}
+
+		let reasoning_effort = match request.thinking {
+			ThinkingLevel::None => None,
+			ThinkingLevel::Low => Some("low"),
+			ThinkingLevel::Medium => Some("medium"),
+			ThinkingLevel::High => Some("high"),
+		};
+		let mut payload = json!({
+			"model": self.model,
+			"messages": messages,
+			"stream": stream,
+		});
+		let obj = payload.as_object_mut().unwrap();
+		// reasoning models reject any temperature but their own, so the default of 0 is only sent without reasoning
+		if let Some(temperature) = request.temperature.or(reasoning_effort.is_none().then_some(0.0)) {
+			obj.insert("temperature".to_string(), json!(temperature));
+		}
+		if stream {
+			obj.insert("stream_options".to_string(), json!({"include_usage": true}));
+		}
+		// reasoning models reject `max_tokens` as well, in favour of a limit that includes the reasoning
+		if let Some(max_tokens) = request.max_tokens {
+			let key = match reasoning_effort {
+				Some(_) => "max_completion_tokens",
+				None => "max_tokens",
+			};
+			obj.insert(key.to_string(), json!(max_tokens));
+		}
+		if let Some(ref stop_seqs) = request.stop_sequences {
+			obj.insert("stop".to_string(), json!(stop_seqs));
+		}
+		match request.json_schema {
+			Some(schema) => {
+				obj.insert(
+					"response_format".to_string(),
+					json!({"type": "json_schema", "json_schema": {"name": "response", "schema": schema}}),
+				);
+			}
+			None if request.force_json => {
+				obj.insert("response_format".to_string(), json!({"type": "json_object"}));
+			}
+			None => {}
+		}
+		if let Some(effort) = reasoning_effort {
+			obj.insert("reasoning_effort".to_string(), json!(effort));
+		}
+		Ok(payload)
+	}
+}
+
+fn unsupported_tools() -> Error {
+	Error::UnsupportedContent {
+		backend: "OpenAI-compatible",
+		detail: "tool calling".to_string(),
+	}
+}
+
+impl Backend for OpenAi {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(self.do_conversation(request))
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		format!("openai:{}", self.model)
+	}
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiMessage {
+	role: &'static str,
+	content: OpenAiContent,
+}
+impl OpenAiMessage {
+	fn new(role: Role, content: &MessageContent) -> Result<Self> {
+		let role = match role {
+			Role::System => "system",
+			Role::User => "user",
+			Role::Assistant => "assistant",
+		};
+		let content = match content {
+			MessageContent::Text(text) => OpenAiContent::Text(text.clone()),
+			MessageContent::Image { base64_data, media_type } => OpenAiContent::Parts(vec![OpenAiPart::image(media_type, base64_data)]),
+			MessageContent::TextAndImages { text, images } => {
+				let mut parts = vec![OpenAiPart::Text { text: text.clone() }];
+				parts.extend(images.iter().map(|img| OpenAiPart::image(&img.media_type, &img.base64_data)));
+				OpenAiContent::Parts(parts)
+			}
+			MessageContent::Document { base64_data, media_type } => OpenAiContent::Parts(vec![OpenAiPart::file(media_type, base64_data)]),
+			MessageContent::Mixed { parts } => OpenAiContent::Parts(
+				parts
+					.iter()
+					.map(|part| match part {
+						ContentPart::Text(text) => OpenAiPart::Text { text: text.clone() },
+						ContentPart::Image { base64_data, media_type } => OpenAiPart::image(media_type, base64_data),
+						ContentPart::Document { base64_data, media_type } => OpenAiPart::file(media_type, base64_data),
+					})
+					.collect(),
+			),
+			MessageContent::ToolUse { .. } | MessageContent::ToolResults(_) => return Err(unsupported_tools()),
+		};
+		Ok(Self { role, content })
+	}
+
+	fn push_text(&mut self, text: &str) {
+		match &mut self.content {
+			OpenAiContent::Text(existing) => existing.push_str(text),
+			OpenAiContent::Parts(parts) => parts.push(OpenAiPart::Text { text: text.to_string() }),
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OpenAiContent {
+	Text(String),
+	Parts(Vec<OpenAiPart>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiPart {
+	Text { text: String },
+	ImageUrl { image_url: ImageUrl },
+	File { file: FileData },
+}
+impl OpenAiPart {
+	fn image(media_type: &str, base64_data: &str) -> Self {
+		Self::ImageUrl {
+			image_url: ImageUrl {
+				url: format!("data:{media_type};base64,{base64_data}"),
+			},
+		}
+	}
+
+	fn file(media_type: &str, base64_data: &str) -> Self {
+		Self::File {
+			file: FileData {
+				filename: format!("attachment.{}", media_type.rsplit('/').next().unwrap_or("bin")),
+				file_data: format!("data:{media_type};base64,{base64_data}"),
+			},
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+struct ImageUrl {
+	url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FileData {
+	filename: String,
+	file_data: String,
+}
+
+/// Same policy as the Claude backend: PDFs and images are sent as binary parts, text-based files are decoded and inlined.
+fn file_to_part(file: &FileAttachment) -> OpenAiPart {
+	use base64::Engine;
+	match file.media_type.as_str() {
+		"application/pdf" => OpenAiPart::file(&file.media_type, &file.base64_data),
+		mt if mt.starts_with("image/") => OpenAiPart::image(&file.media_type, &file.base64_data),
+		_ => {
+			let decoded = base64::engine::general_purpose::STANDARD
+				.decode(&file.base64_data)
+				.ok()
+				.and_then(|bytes| String::from_utf8(bytes).ok())
+				.unwrap_or_else(|| format!("[Binary file: {}]", file.media_type));
+			OpenAiPart::Text { text: decoded }
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletion {
+	choices: Vec<Choice>,
+	usage: Option<CompletionUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+	message: ChoiceMessage,
+	finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChoiceMessage {
+	content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionUsage {
+	#[serde(default)]
+	prompt_tokens: u32,
+	#[serde(default)]
+	completion_tokens: u32,
+	prompt_tokens_details: Option<PromptTokensDetails>,
+	completion_tokens_details: Option<CompletionTokensDetails>,
+}
+impl From<CompletionUsage> for Usage {
+	fn from(usage: CompletionUsage) -> Self {
+		// `prompt_tokens` includes the cached ones
+		let cache_read = usage.prompt_tokens_details.map(|d| d.cached_tokens).unwrap_or_default();
+		Self {
+			input: usage.prompt_tokens.saturating_sub(cache_read),
+			output: usage.completion_tokens,
+			cache_read,
+			cache_write: 0,
+			thinking: usage.completion_tokens_details.map(|d| d.reasoning_tokens).unwrap_or_default(),
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptTokensDetails {
+	#[serde(default)]
+	cached_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionTokensDetails {
+	#[serde(default)]
+	reasoning_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+	#[serde(default)]
+	choices: Vec<ChunkChoice>,
+	usage: Option<CompletionUsage>,
+	/// Failure after the 200 was sent
+	error: Option<ChunkError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkError {
+	#[serde(default)]
+	message: String,
+	#[serde(rename = "type")]
+	kind: Option<String>,
+	/// A string like `rate_limit_exceeded` from OpenAI, an HTTP status from OpenRouter
+	code: Option<Value>,
+}
+impl From<ChunkError> for Error {
+	/// Classified by the HTTP status the same error gets before streaming starts.
+	fn from(error: ChunkError) -> Self {
+		let kind = error.code.as_ref().and_then(Value::as_str).or(error.kind.as_deref());
+		let status = match (error.code.as_ref().and_then(Value::as_u64), kind) {
+			(Some(code @ 400..600), _) => code as u16,
+			(_, Some("rate_limit_exceeded" | "rate_limit_error")) => 429,
+			(_, Some("context_length_exceeded" | "invalid_request_error")) => 400,
+			_ => 500,
+		};
+		let message = match kind {
+			Some(kind) => format!("{kind}: {}", error.message),
+			None => error.message,
+		};
+		Error::from_status(status, None, message)
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+	#[serde(default)]
+	delta: ChunkDelta,
+	finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+	content: Option<String>,
+	/// Not part of the OpenAI spec; DeepSeek, vLLM and llama.cpp use `reasoning_content`, OpenRouter and Groq use `reasoning`
+	#[serde(alias = "reasoning")]
+	reasoning_content: Option<String>,
+}
+
+/// Turn a streaming chat completion into [StreamEvent]s.
+///
+/// With `include_usage`, the usage chunk arrives after the one carrying `finish_reason`, so `Stop` is held back until `[DONE]`.
+fn sse_events(response: reqwest::Response) -> ResponseStream {
+	struct State {
+		stop_reason: Option<String>,
+		done: bool,
+	}
+	impl State {
+		fn stop(&mut self, out: &mut Vec<StreamEvent>) {
+			if !self.done {
+				self.done = true;
+				out.push(StreamEvent::Stop {
+					reason: self.stop_reason.take(),
+					stop_sequence: None,
+				});
+			}
+		}
+	}
+	impl crate::sse::Translate for State {
+		fn event(&mut self, raw: crate::sse::Event, out: &mut Vec<StreamEvent>) -> Result<()> {
+			let data = raw.data.trim();
+			if data == "[DONE]" {
+				self.stop(out);
+				return Ok(());
+			}
+			let chunk: ChatCompletionChunk = serde_json::from_str(data)?;
+			if let Some(error) = chunk.error {
+				return Err(error.into());
+			}
+			for choice in chunk.choices {
+				if let Some(thinking) = choice.delta.reasoning_content.filter(|t| !t.is_empty()) {
+					out.push(StreamEvent::Thinking(thinking));
+				}
+				if let Some(text) = choice.delta.content.filter(|t| !t.is_empty()) {
+					out.push(StreamEvent::Text(text));
+				}
+				if choice.finish_reason.as_deref() == Some("content_filter") {
+					return Err(Error::Refusal("content_filter".to_string()));
+				}
+				if choice.finish_reason.is_some() {
+					self.stop_reason = choice.finish_reason;
+				}
+			}
+			if let Some(usage) = chunk.usage {
+				out.push(StreamEvent::Usage(usage.into()));
+			}
+			Ok(())
+		}
+
+		/// Some servers close the connection without sending `[DONE]`
+		fn end(&mut self, out: &mut Vec<StreamEvent>) {
+			if self.stop_reason.is_some() {
+				self.stop(out);
+			}
+		}
+	}
+
+	crate::sse::events(response.bytes_stream(), State { stop_reason: None, done: false })
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::StreamExt as _;
+
+	use super::*;
+	use crate::{
+		Conversation, Message,
+		testing::{fake_server, request},
+	};
+
+	#[test]
+	fn payload_maps_images_and_json() {
+		let backend = OpenAi {
+			base_url: "http://localhost:8080/v1".to_string(),
+			api_key: None,
+			model: "local".to_string(),
+		};
+		let mut conv = Conversation::new_with_system("be terse");
+		conv.0.push(Message::new_with_image(Role::User, "AAAA".to_string(), "image/png".to_string()));
+		let request = Request {
+			max_tokens: Some(10),
+			stop_sequences: Some(vec!["END"]),
+			force_json: true,
+			..request(&conv)
+		};
+
+		let payload = backend.payload(&request, false).unwrap();
+		assert_eq!(payload["messages"][0], json!({"role": "system", "content": "be terse"}));
+		assert_eq!(payload["messages"][1]["content"][0]["image_url"]["url"], "data:image/png;base64,AAAA");
+		assert_eq!(payload["messages"][1]["content"][1]["type"], "text");
+		assert_eq!(payload["response_format"]["type"], "json_object");
+		assert_eq!(payload["stop"], json!(["END"]));
+		assert_eq!(payload["max_tokens"], 10);
+		assert_eq!(payload["temperature"], 0.0);
+
+		let reasoning = Request {
+			thinking: ThinkingLevel::Low,
+			stop_sequences: None,
+			..request
+		};
+		let payload = backend.payload(&reasoning, false).unwrap();
+		assert_eq!(payload["reasoning_effort"], "low");
+		assert!(payload.get("temperature").is_none());
+		assert!(payload.get("max_tokens").is_none());
+		assert_eq!(payload["max_completion_tokens"], 10);
+	}
+
+	#[tokio::test]
+	async fn stream_errors() {
+		async fn events(body: &'static str) -> Vec<Result<StreamEvent>> {
+			let (url, _server) = fake_server("text/event-stream", body).await;
+			let backend = OpenAi {
+				base_url: url,
+				api_key: None,
+				model: "local".to_string(),
+			};
+			let mut conv = Conversation::new();
+			conv.add(Role::User, "hi");
+			backend.do_stream(&request(&conv)).await.unwrap().collect().await
+		}
+
+		let overloaded = events(concat!(
+			"data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+			"data: {\"error\":{\"message\":\"Provider overloaded\",\"code\":503}}\n\n",
+		))
+		.await;
+		assert!(matches!(&overloaded[0], Ok(StreamEvent::Text(t)) if t == "Hel"));
+		assert!(matches!(overloaded[1], Err(Error::Overloaded)));
+
+		let filtered = events("data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"content_filter\"}]}\n\ndata: [DONE]\n\n").await;
+		assert!(matches!(filtered[0], Err(Error::Refusal(_))));
+	}
+}
@@ -0,0 +1,178 @@
+//! Assistant prefill: handing the model the start of its reply to continue from, see [Conversation::prefill].
+
+use futures::StreamExt as _;
+
+use crate::{Conversation, Message, MessageContent, Response, ResponseStream, Role, StreamEvent};
+
+/// Follows the prefix on backends without native prefill
+const CONTINUE: &str = "Continue your last reply from exactly where it stops, without repeating any of it.";
+
+/// `conv` ready to send, and the prefix its reply continues, if it ends in a prefill.
+/// With `native` prefill the prefix stays the last message; Claude rejects one ending in whitespace, so that's trimmed off.
+/// Otherwise the model is asked to continue it.
+pub(crate) fn prepare(conv: &Conversation, native: bool) -> Option<(Conversation, String)> {
+	let Some(Message {
+		role: Role::Assistant,
+		content: MessageContent::Text(prefix),
+		..
+	}) = conv.0.last()
+	else {
+		return None;
+	};
+	let mut conv = conv.clone();
+	let prefix = match native {
+		true => prefix.trim_end().to_string(),
+		false => prefix.clone(),
+	};
+	match native {
+		true => conv.0.last_mut().unwrap().content = MessageContent::Text(prefix.clone()),
+		false => conv.0.push(Message::new(Role::User, CONTINUE)),
+	}
+	Some((conv, prefix))
+}
+
+/// The reply to a [prepare]d conversation, with its prefix re-joined. An emulated continuation sometimes restates the prefix first.
+pub(crate) fn join(prefix: &str, text: &str, native: bool) -> String {
+	let text = match native {
+		true => text,
+		false => text.strip_prefix(prefix).unwrap_or(text),
+	};
+	format!("{prefix}{text}")
+}
+
+/// [join] for a stream: the prefix comes first, and an emulated continuation's text is held back until it's clear whether it restates it.
+pub(crate) fn join_stream(prefix: String, stream: ResponseStream, native: bool) -> ResponseStream {
+	struct Joining {
+		stream: futures::stream::Fuse<ResponseStream>,
+		/// Until the start of the reply is known
+		prefix: Option<String>,
+		text: String,
+		pending: std::collections::VecDeque<crate::Result<StreamEvent>>,
+	}
+	impl Joining {
+		fn resolve(&mut self) {
+			if let Some(prefix) = self.prefix.take() {
+				self.pending.push_back(Ok(StreamEvent::Text(join(&prefix, &std::mem::take(&mut self.text), false))));
+			}
+		}
+	}
+
+	if native {
+		return Box::pin(futures::stream::once(std::future::ready(Ok(StreamEvent::Text(prefix)))).chain(stream));
+	}
+	let state = Joining {
+		stream: stream.fuse(),
+		prefix: Some(prefix),
+		text: String::new(),
+		pending: Default::default(),
+	};
+	Box::pin(futures::stream::unfold(state, |mut state| async move {
+		loop {
+			if let Some(event) = state.pending.pop_front() {
+				return Some((event, state));
+			}
+			let Some(prefix) = &state.prefix else {
+				return state.stream.next().await.map(|event| (event, state));
+			};
+			match state.stream.next().await {
+				Some(Ok(StreamEvent::Text(text))) => {
+					state.text.push_str(&text);
+					if state.text.len() >= prefix.len() || !prefix.starts_with(state.text.as_str()) {
+						state.resolve();
+					}
+				}
+				Some(Ok(event @ (StreamEvent::Thinking(_) | StreamEvent::ThinkingBlock(_) | StreamEvent::Usage(_) | StreamEvent::Backend { .. }))) => {
+					state.pending.push_back(Ok(event));
+				}
+				event => {
+					state.resolve();
+					state.pending.extend(event);
+				}
+			}
+		}
+	}))
+}
+
+/// `first` continued by `rest`, which already contains its text, as one reply: usage, cost and duration added up, the stop as `rest` reports it.
+pub(crate) fn stitch(first: Response, rest: Response) -> Response {
+	let mut usage = first.usage;
+	usage += rest.usage;
+	Response {
+		cost_cents: first.cost_cents + rest.cost_cents,
+		usage,
+		duration: first.duration + rest.duration,
+		overhead: first.overhead,
+		tool_calls: first.tool_calls.into_iter().chain(rest.tool_calls).collect(),
+		skipped: first.skipped.into_iter().chain(rest.skipped).collect(),
+		cache_hit: first.cache_hit && rest.cache_hit,
+		thinking_blocks: first.thinking_blocks.into_iter().chain(rest.thinking_blocks).collect(),
+		..rest
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Client, MessageContent, config::AppConfig, testing::MockBackend};
+
+	#[tokio::test]
+	async fn auto_continue_stitches() {
+		let truncated = |text: &str, stop: &str| Response {
+			text: text.to_string(),
+			cost_cents: 1.0,
+			stop_reason: Some(stop.to_string()),
+			..Default::default()
+		};
+		let mock = MockBackend::new().respond(truncated("1, 2, ", "length")).respond(truncated("1, 2, 3", "stop"));
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), mock).auto_continue(2);
+
+		let response = client.ask("count to 3").await.unwrap();
+		assert_eq!(response.text, "1, 2, 3");
+		assert_eq!(response.cost_cents, 2.0);
+		assert!(!response.is_truncated());
+		let continued = &requests.last().unwrap().conversation.0;
+		assert!(matches!(continued[1].content(), MessageContent::Text(t) if t == "1, 2, "));
+		assert!(matches!(continued[2].content(), MessageContent::Text(t) if t == CONTINUE));
+
+		assert_eq!(continued.len(), 3, "the continuation replaces the first prefill");
+	}
+
+	#[tokio::test]
+	async fn streamed_prefix_is_rejoined() {
+		let text = |chunks: &[&str]| {
+			let events = chunks.iter().map(|t| Ok(StreamEvent::Text(t.to_string()))).collect::<Vec<_>>();
+			join_stream("<answer>".to_string(), Box::pin(futures::stream::iter(events)), false)
+				.map(|event| match event.unwrap() {
+					StreamEvent::Text(t) => t,
+					_ => String::new(),
+				})
+				.collect::<String>()
+		};
+		assert_eq!(text(&["<ans", "wer>4", "2</answer>"]).await, "<answer>42</answer>");
+		assert_eq!(text(&["42", "</answer>"]).await, "<answer>42</answer>");
+		assert_eq!(text(&["<an"]).await, "<answer><an");
+
+		let client = Client::with_backend(AppConfig::default(), MockBackend::new().reply("<answer>42</answer>")).prefill("<answer>");
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "6*7?");
+		assert_eq!(client.conversation_streamed(&conv, |_| {}).await.unwrap().text, "<answer>42</answer>");
+	}
+
+	#[tokio::test]
+	async fn prefix_is_rejoined() {
+		let mock = MockBackend::new().reply("<answer>42</answer>").reply("7</answer>");
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), mock).prefill("<answer>");
+		// emulated continuations may restate the prefix
+		assert_eq!(client.ask("6*7?").await.unwrap().text, "<answer>42</answer>");
+		assert_eq!(client.ask("3+4?").await.unwrap().text, "<answer>7</answer>");
+		assert!(matches!(requests.last().unwrap().conversation.0[1].content(), MessageContent::Text(t) if t == "<answer>"));
+
+		let mut conv = Conversation::new();
+		conv.add(Role::User, "code please");
+		conv.prefill("```rust\n");
+		let (native, prefix) = prepare(&conv, true).unwrap();
+		assert_eq!((native.0.len(), prefix.as_str()), (2, "```rust"));
+	}
+}
@@ -0,0 +1,99 @@
+use std::{collections::hash_map::RandomState, future::Future, hash::BuildHasher, time::Duration};
+
+use crate::{Error, Result};
+
+/// When and how failed requests are sent again, see [Client::retry](crate::Client::retry).
+///
+/// ```ignore
+/// let client = Client::default().retry(RetryPolicy {
+/// 	max_attempts: 5,
+/// 	mid_stream: MidStreamPolicy::Propagate,
+/// 	..Default::default()
+/// });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RetryPolicy {
+	/// Total attempts, the first one included. `1` disables retrying.
+	pub max_attempts: u32 = 3,
+	/// Wait before the second attempt; each following one waits `multiplier` times longer.
+	pub initial_backoff: Duration = Duration::from_millis(500),
+	pub multiplier: f32 = 2.0,
+	/// Upper bound on any single wait. A `retry-after` longer than this is returned as the error instead of waited out.
+	pub max_backoff: Duration = Duration::from_secs(60),
+	/// Each backoff is randomized by up to this fraction in either direction.
+	pub jitter: f32 = 0.2,
+	/// Which errors are worth another attempt.
+	pub retryable: fn(&Error) -> bool = Error::is_retryable,
+	pub mid_stream: MidStreamPolicy = MidStreamPolicy::RetryBeforeOutput,
+}
+
+/// What to do when a streamed reply fails after the connection was established.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MidStreamPolicy {
+	/// Only failures to open the stream are retried; later errors are yielded to the consumer.
+	Propagate,
+	/// Also reconnect if the stream breaks before any text, thinking or tool call came through. The consumer never sees the failed attempt.
+	#[default]
+	RetryBeforeOutput,
+}
+
+impl RetryPolicy {
+	/// Fail on the first error.
+	pub fn none() -> Self {
+		Self { max_attempts: 1, .. }
+	}
+
+	/// Call `f` until it succeeds, the error is not retryable, or attempts run out.
+	pub(crate) async fn run<T, F, Fut>(&self, mut f: F) -> Result<T>
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T>>,
+	{
+		let mut attempt = 1;
+		loop {
+			let e = match f().await {
+				Ok(value) => return Ok(value),
+				Err(e) => e,
+			};
+			let Some(delay) = self.delay(attempt, &e) else {
+				return Err(e);
+			};
+			tracing::warn!("Attempt {attempt}/{} failed, retrying in {:.1}s: {e}", self.max_attempts, delay.as_secs_f32());
+			tokio::time::sleep(delay).await;
+			attempt += 1;
+		}
+	}
+
+	/// Wait before the attempt following `attempt`, or `None` if `error` should be returned as is.
+	fn delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+		if attempt >= self.max_attempts || !(self.retryable)(error) {
+			return None;
+		}
+		if let Error::RateLimited { retry_after: Some(retry_after) } = error {
+			return (*retry_after <= self.max_backoff).then_some(*retry_after);
+		}
+		let backoff = (self.initial_backoff.as_secs_f32() * self.multiplier.powi(attempt as i32 - 1)).min(self.max_backoff.as_secs_f32());
+		let spread = RandomState::new().hash_one(attempt) as f32 / u64::MAX as f32 * 2.0 - 1.0;
+		Some(Duration::from_secs_f32((backoff * (1.0 + self.jitter * spread)).max(0.0)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn delays() {
+		let policy = RetryPolicy { jitter: 0.0, .. };
+		assert_eq!(policy.delay(1, &Error::Overloaded), Some(Duration::from_millis(500)));
+		assert_eq!(policy.delay(2, &Error::Overloaded), Some(Duration::from_secs(1)));
+		assert_eq!(policy.delay(3, &Error::Overloaded), None);
+		assert_eq!(policy.delay(1, &Error::Refusal(String::new())), None);
+
+		let rate_limited = |secs| Error::RateLimited {
+			retry_after: Some(Duration::from_secs(secs)),
+		};
+		assert_eq!(policy.delay(1, &rate_limited(7)), Some(Duration::from_secs(7)));
+		assert_eq!(policy.delay(1, &rate_limited(3600)), None);
+	}
+}
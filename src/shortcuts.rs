@@ -1,6 +1,4 @@
-use eyre::Result;
-
-use crate::{Client, Conversation, Model, Response};
+use crate::{Client, Conversation, Model, Response, Result};
 
 pub async fn oneshot(message: impl Into<String>) -> Result<Response> {
 	Client::default().ask(message).await
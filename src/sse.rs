@@ -0,0 +1,217 @@
+//! Server-sent events framing, as used by the Claude, OpenAI and Gemini streaming endpoints.
+//! ref: https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+
+use futures::StreamExt as _;
+
+use crate::{ResponseStream, Result, StreamEvent};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Event {
+	/// `event:` field, `None` for the default `message` type
+	pub event: Option<String>,
+	/// `data:` lines, joined by `\n`
+	pub data: String,
+}
+
+/// Incremental decoder. Bytes are buffered until a whole line is available, so chunks may split events, lines,
+/// `\r\n` pairs and multi-byte characters anywhere. Invalid UTF-8 is replaced, not fatal.
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+	buffer: Vec<u8>,
+	event: Option<String>,
+	/// `None` until the current event has a `data:` line; events without one are not dispatched
+	data: Option<String>,
+}
+impl Decoder {
+	/// Feed the next chunk, returning the events it completed.
+	pub fn push(&mut self, chunk: &[u8]) -> Vec<Event> {
+		self.buffer.extend_from_slice(chunk);
+		let mut events = Vec::new();
+		let mut start = 0;
+		let mut i = 0;
+		while i < self.buffer.len() {
+			let next = match self.buffer[i] {
+				b'\n' => i + 1,
+				// could be the first half of a `\r\n` split across chunks
+				b'\r' if i + 1 == self.buffer.len() => break,
+				b'\r' if self.buffer[i + 1] == b'\n' => i + 2,
+				b'\r' => i + 1,
+				_ => {
+					i += 1;
+					continue;
+				}
+			};
+			let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+			events.extend(self.line(&line));
+			start = next;
+			i = next;
+		}
+		self.buffer.drain(..start);
+		events
+	}
+
+	/// The event in progress when the stream ended. The spec discards it, but some servers don't terminate the last event.
+	pub fn finish(&mut self) -> Option<Event> {
+		let rest = std::mem::take(&mut self.buffer);
+		let rest = String::from_utf8_lossy(&rest);
+		let rest = rest.trim_end_matches('\r');
+		if !rest.is_empty() {
+			self.line(rest);
+		}
+		self.line("")
+	}
+
+	fn line(&mut self, line: &str) -> Option<Event> {
+		if line.is_empty() {
+			let event = self.event.take();
+			return self.data.take().map(|data| Event { event, data });
+		}
+		// comment, eg keep-alives
+		if line.starts_with(':') {
+			return None;
+		}
+		let (field, value) = match line.split_once(':') {
+			Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+			None => (line, ""),
+		};
+		match field {
+			"event" => self.event = Some(value.to_string()),
+			"data" => match &mut self.data {
+				Some(data) => {
+					data.push('\n');
+					data.push_str(value);
+				}
+				None => self.data = Some(value.to_string()),
+			},
+			// `id` and `retry` only matter for reconnecting, which we don't do
+			_ => {}
+		}
+		None
+	}
+}
+
+/// A provider's translation of decoded [Event]s into [StreamEvent]s, see [events].
+pub(crate) trait Translate: Send + 'static {
+	/// Push what `event` amounts to onto `out`. An error is yielded after those.
+	fn event(&mut self, event: Event, out: &mut Vec<StreamEvent>) -> Result<()>;
+
+	/// Push anything still owed once the body ends.
+	fn end(&mut self, _out: &mut Vec<StreamEvent>) {}
+}
+
+/// [StreamEvent]s of an event stream `bytes`, as `translate` reads them.
+pub(crate) fn events(bytes: impl futures::Stream<Item = reqwest::Result<bytes::Bytes>> + Send + 'static, translate: impl Translate) -> ResponseStream {
+	struct Pump<T> {
+		bytes: futures::stream::BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+		decoder: Decoder,
+		translate: T,
+		pending: std::collections::VecDeque<Result<StreamEvent>>,
+		ended: bool,
+	}
+	impl<T: Translate> Pump<T> {
+		/// Whether `event` translated without error; events before a failure still go out ahead of it.
+		fn push(&mut self, event: Event) -> bool {
+			let mut out = Vec::new();
+			let result = self.translate.event(event, &mut out);
+			self.pending.extend(out.into_iter().map(Ok));
+			match result {
+				Ok(()) => true,
+				Err(e) => {
+					self.pending.push_back(Err(e));
+					false
+				}
+			}
+		}
+	}
+
+	let pump = Pump {
+		bytes: bytes.fuse().boxed(),
+		decoder: Decoder::default(),
+		translate,
+		pending: Default::default(),
+		ended: false,
+	};
+	Box::pin(futures::stream::unfold(pump, |mut pump| async move {
+		loop {
+			if let Some(event) = pump.pending.pop_front() {
+				return Some((event, pump));
+			}
+			if pump.ended {
+				return None;
+			}
+			match pump.bytes.next().await {
+				Some(Ok(chunk)) => {
+					for event in pump.decoder.push(&chunk) {
+						if !pump.push(event) {
+							break;
+						}
+					}
+				}
+				Some(Err(e)) => return Some((Err(e.into()), pump)),
+				None => {
+					pump.ended = true;
+					if let Some(event) = pump.decoder.finish() {
+						pump.push(event);
+					}
+					let mut out = Vec::new();
+					pump.translate.end(&mut out);
+					pump.pending.extend(out.into_iter().map(Ok));
+				}
+			}
+		}
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const STREAM: &str = "event: greeting\r\ndata: héllo\r\ndata:  wörld 日本\r\n\r\n: keep-alive\n\nevent: empty\n\ndata: {\"a\":1}\r\rdata: last";
+
+	fn expected() -> Vec<Event> {
+		vec![
+			Event {
+				event: Some("greeting".to_string()),
+				data: "héllo\n wörld 日本".to_string(),
+			},
+			Event {
+				event: None,
+				data: "{\"a\":1}".to_string(),
+			},
+			Event {
+				event: None,
+				data: "last".to_string(),
+			},
+		]
+	}
+
+	fn decode(chunks: &[&[u8]]) -> Vec<Event> {
+		let mut decoder = Decoder::default();
+		let mut events: Vec<Event> = chunks.iter().flat_map(|chunk| decoder.push(chunk)).collect();
+		events.extend(decoder.finish());
+		events
+	}
+
+	#[test]
+	fn any_chunking() {
+		let bytes = STREAM.as_bytes();
+		assert_eq!(decode(&[bytes]), expected());
+		// every split point, which cuts through `\r\n`s and multi-byte characters
+		for i in 0..bytes.len() {
+			assert_eq!(decode(&[&bytes[..i], &bytes[i..]]), expected(), "split at {i}");
+		}
+		let single_bytes: Vec<&[u8]> = bytes.chunks(1).collect();
+		assert_eq!(decode(&single_bytes), expected());
+	}
+
+	#[test]
+	fn invalid_utf8_is_replaced() {
+		assert_eq!(
+			decode(&[b"data: \xff\n\n"]),
+			vec![Event {
+				event: None,
+				data: "\u{FFFD}".to_string()
+			}]
+		);
+	}
+}
@@ -0,0 +1,452 @@
+//! Backends for testing code built on `ask_llm` without network access.
+//!
+//! ```ignore
+//! let mock = MockBackend::new().reply("4");
+//! let requests = mock.requests();
+//! let client = Client::with_backend(AppConfig::default(), mock);
+//! assert_eq!(client.ask("2+2?").await?.text, "4");
+//! assert_eq!(requests.last().unwrap().conversation.0.len(), 1);
+//! ```
+use std::{
+	collections::{HashMap, VecDeque},
+	future::Future,
+	path::PathBuf,
+	pin::Pin,
+	sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Backend, Conversation, DiskCache, Error, FileAttachment, Request, Response, ResponseStream, Result, StreamEvent, ThinkingLevel, ToolCall, disk_cache::Entry};
+
+/// Answers with scripted replies, in order, and keeps every [Request] it receives. Use with [Client::with_backend](crate::Client::with_backend).
+///
+/// Running out of replies is an [Error::Config].
+#[derive(Default)]
+pub struct MockBackend {
+	replies: Mutex<VecDeque<Result<Response>>>,
+	requests: Requests,
+}
+impl MockBackend {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Queue a plain text reply.
+	pub fn reply(self, text: impl Into<String>) -> Self {
+		self.respond(Response {
+			text: text.into(),
+			model: "mock".to_string(),
+			..Default::default()
+		})
+	}
+
+	/// Queue a reply calling a tool.
+	pub fn tool_call(self, name: impl Into<String>, input: serde_json::Value) -> Self {
+		let id = format!("mock_call_{}", self.replies.lock().unwrap().len());
+		self.respond(Response {
+			tool_calls: vec![ToolCall { id, name: name.into(), input }],
+			model: "mock".to_string(),
+			..Default::default()
+		})
+	}
+
+	pub fn respond(self, response: Response) -> Self {
+		self.replies.lock().unwrap().push_back(Ok(response));
+		self
+	}
+
+	/// Queue a failure, eg [Error::Overloaded] to exercise retries.
+	pub fn fail(self, error: Error) -> Self {
+		self.replies.lock().unwrap().push_back(Err(error));
+		self
+	}
+
+	/// Handle on the requests received so far; stays valid after the backend is moved into a client.
+	pub fn requests(&self) -> Requests {
+		self.requests.clone()
+	}
+
+	fn next(&self, request: &Request<'_>) -> Result<Response> {
+		self.requests.0.lock().unwrap().push(RecordedRequest::new(request));
+		self.replies
+			.lock()
+			.unwrap()
+			.pop_front()
+			.unwrap_or_else(|| Err(Error::Config("MockBackend has no scripted replies left".to_string())))
+	}
+}
+
+impl Backend for MockBackend {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(async move { self.next(request) })
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(async move { self.next(request).map(events) })
+	}
+
+	fn name(&self) -> String {
+		"mock:mock".to_string()
+	}
+}
+
+/// Requests a [MockBackend] received, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct Requests(Arc<Mutex<Vec<RecordedRequest>>>);
+impl Requests {
+	pub fn all(&self) -> Vec<RecordedRequest> {
+		self.0.lock().unwrap().clone()
+	}
+
+	pub fn last(&self) -> Option<RecordedRequest> {
+		self.0.lock().unwrap().last().cloned()
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+/// Owned copy of a [Request], for assertions.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+	pub conversation: Conversation,
+	pub temperature: Option<f32>,
+	pub max_tokens: Option<usize>,
+	pub stop_sequences: Option<Vec<String>>,
+	pub force_json: bool,
+	pub json_schema: Option<serde_json::Value>,
+	pub files: Vec<FileAttachment>,
+	pub thinking: ThinkingLevel,
+	/// Names of the declared tools
+	pub tools: Vec<String>,
+}
+impl RecordedRequest {
+	fn new(request: &Request<'_>) -> Self {
+		Self {
+			conversation: request.conversation.clone(),
+			temperature: request.temperature,
+			max_tokens: request.max_tokens,
+			stop_sequences: request.stop_sequences.as_ref().map(|s| s.iter().map(|s| s.to_string()).collect()),
+			force_json: request.force_json,
+			json_schema: request.json_schema.cloned(),
+			files: request.files.to_vec(),
+			thinking: request.thinking,
+			tools: request.tools.iter().map(|t| t.name.clone()).collect(),
+		}
+	}
+}
+
+/// Whether a [fixture](crate::Client::fixture) talks to the real backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FixtureMode {
+	/// Replay what's recorded, record what isn't
+	#[default]
+	Auto,
+	/// Only replay; an unrecorded request is an [Error::Config]. For CI.
+	Replay,
+	/// Always ask the real backend, replacing recorded replies
+	Record,
+}
+
+/// Records exchanges with a real backend to a JSON file and replays them, so tests are deterministic and need no network or keys.
+/// See [Client::fixture](crate::Client::fixture).
+///
+/// Requests are matched by everything that affects the reply, as for [DiskCache]; identical requests replay their replies in recorded order.
+/// Streams are recorded whole, so while recording they arrive all at once.
+pub(crate) struct Fixture {
+	pub inner: Box<dyn Backend>,
+	pub path: PathBuf,
+	pub mode: FixtureMode,
+	state: Mutex<FixtureState>,
+}
+
+#[derive(Default)]
+struct FixtureState {
+	exchanges: Vec<Exchange>,
+	/// Replies of each key replayed so far
+	replayed: HashMap<String, usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FixtureFile {
+	version: u32,
+	exchanges: Vec<Exchange>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Exchange {
+	key: String,
+	/// Not read back; there to make fixtures reviewable
+	request: serde_json::Value,
+	response: Entry,
+}
+
+impl Fixture {
+	pub fn new(inner: Box<dyn Backend>, path: PathBuf, mode: FixtureMode) -> Result<Self> {
+		let exchanges = match (mode, std::fs::read_to_string(&path)) {
+			(FixtureMode::Record, _) => Vec::new(),
+			(_, Ok(json)) => serde_json::from_str::<FixtureFile>(&json)?.exchanges,
+			(_, Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+			(_, Err(e)) => return Err(e.into()),
+		};
+		Ok(Self {
+			inner,
+			path,
+			mode,
+			state: Mutex::new(FixtureState { exchanges, ..Default::default() }),
+		})
+	}
+
+	fn replay(&self, key: &str) -> Option<Response> {
+		let mut state = self.state.lock().unwrap();
+		let index = *state.replayed.get(key).unwrap_or(&0);
+		let entry = state.exchanges.iter().filter(|e| e.key == key).nth(index)?.response.clone();
+		*state.replayed.entry(key.to_string()).or_default() += 1;
+		Some(entry.into_response())
+	}
+
+	fn record(&self, key: String, request: &Request<'_>, response: &Response) -> Result<()> {
+		let mut state = self.state.lock().unwrap();
+		// the nth identical request of a run maps to the nth reply, recorded or not
+		*state.replayed.entry(key.clone()).or_default() += 1;
+		state.exchanges.push(Exchange {
+			key,
+			request: DiskCache::canonical(&self.inner.name(), request),
+			response: Entry::new(response),
+		});
+		if let Some(dir) = self.path.parent() {
+			std::fs::create_dir_all(dir)?;
+		}
+		let file = FixtureFile {
+			version: 1,
+			exchanges: state.exchanges.clone(),
+		};
+		std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+		Ok(())
+	}
+
+	fn lookup(&self, request: &Request<'_>) -> Result<(String, Option<Response>)> {
+		let key = DiskCache::key(&self.inner.name(), request);
+		let replayed = match self.mode {
+			FixtureMode::Record => None,
+			_ => self.replay(&key),
+		};
+		if replayed.is_none() && self.mode == FixtureMode::Replay {
+			return Err(Error::Config(format!("No recorded reply for this request in {}", self.path.display())));
+		}
+		Ok((key, replayed))
+	}
+
+	async fn do_conversation(&self, request: &Request<'_>) -> Result<Response> {
+		let (key, replayed) = self.lookup(request)?;
+		if let Some(response) = replayed {
+			return Ok(response);
+		}
+		let response = self.inner.conversation(request).await?;
+		self.record(key, request, &response)?;
+		Ok(response)
+	}
+
+	async fn do_stream(&self, request: &Request<'_>) -> Result<ResponseStream> {
+		use futures::StreamExt as _;
+
+		let (key, replayed) = self.lookup(request)?;
+		if let Some(response) = replayed {
+			return Ok(events(response));
+		}
+		let mut stream = self.inner.stream(request).await?;
+		let mut recorded = Vec::new();
+		let mut response = Response {
+			model: self.inner.name(),
+			thinking: request.thinking,
+			..Default::default()
+		};
+		while let Some(event) = stream.next().await {
+			let event = event?;
+			match &event {
+				StreamEvent::Text(text) => response.text.push_str(text),
+				StreamEvent::Usage(usage) => response.usage = *usage,
+				StreamEvent::ToolUse(call) => response.tool_calls.push(call.clone()),
+				StreamEvent::ThinkingBlock(block) => response.thinking_blocks.push(block.clone()),
+				StreamEvent::Stop { reason, stop_sequence } => {
+					response.stop_reason = reason.clone();
+					response.stop_sequence = stop_sequence.clone();
+				}
+				StreamEvent::Backend { name, .. } => response.backend = name.clone(),
+				StreamEvent::Thinking(_) => {}
+			}
+			recorded.push(Ok(event));
+		}
+		self.record(key, request, &response)?;
+		Ok(Box::pin(futures::stream::iter(recorded)))
+	}
+}
+
+impl Backend for Fixture {
+	fn conversation<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<Response>> + Send + 'a>> {
+		Box::pin(self.do_conversation(request))
+	}
+
+	fn stream<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<ResponseStream>> + Send + 'a>> {
+		Box::pin(self.do_stream(request))
+	}
+
+	fn name(&self) -> String {
+		self.inner.name()
+	}
+
+	fn cost_cents(&self, usage: &crate::Usage) -> f32 {
+		self.inner.cost_cents(usage)
+	}
+
+	fn stream_cost_cents(&self, backend: &str, usage: &crate::Usage) -> f32 {
+		self.inner.stream_cost_cents(backend, usage)
+	}
+
+	/// Estimated, so that replaying needs no network.
+	fn count_tokens<'a>(&'a self, request: &'a Request<'a>) -> Pin<Box<dyn Future<Output = Result<u32>> + Send + 'a>> {
+		Box::pin(async move { Ok(request.estimate_tokens()) })
+	}
+
+	fn max_output(&self, request: &Request<'_>) -> Option<u32> {
+		self.inner.max_output(request)
+	}
+
+	fn input_limit(&self, request: &Request<'_>) -> Option<u32> {
+		self.inner.input_limit(request)
+	}
+
+	fn native_prefill(&self, request: &Request<'_>) -> bool {
+		self.inner.native_prefill(request)
+	}
+}
+
+/// `response` as the events a stream of it would have yielded.
+fn events(response: Response) -> ResponseStream {
+	let mut events = Vec::new();
+	if !response.backend.is_empty() {
+		events.push(StreamEvent::Backend {
+			name: response.backend,
+			skipped: response.skipped,
+		});
+	}
+	for block in response.thinking_blocks {
+		if let crate::ThinkingBlock::Thinking { text, .. } = &block {
+			events.push(StreamEvent::Thinking(text.clone()));
+		}
+		events.push(StreamEvent::ThinkingBlock(block));
+	}
+	if !response.text.is_empty() {
+		events.push(StreamEvent::Text(response.text));
+	}
+	events.extend(response.tool_calls.into_iter().map(StreamEvent::ToolUse));
+	events.push(StreamEvent::Usage(response.usage));
+	events.push(StreamEvent::Stop {
+		reason: response.stop_reason,
+		stop_sequence: response.stop_sequence,
+	});
+	Box::pin(futures::stream::iter(events.into_iter().map(Ok)))
+}
+
+/// [Request] for `conv` with the settings of a default [Client](crate::Client).
+#[cfg(test)]
+pub(crate) fn request(conv: &Conversation) -> Request<'_> {
+	Request {
+		conversation: conv,
+		temperature: None,
+		max_tokens: None,
+		stop_sequences: None,
+		force_json: false,
+		json_schema: None,
+		files: &[],
+		thinking: ThinkingLevel::None,
+		tools: &[],
+	}
+}
+
+/// Scratch path under the temp dir, separate per test process; `name` keeps tests apart.
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+	std::env::temp_dir().join(format!("ask_llm_{}_{name}", std::process::id()))
+}
+
+/// Serves exactly one request at the returned `http://host:port` with `body`, returning what the client sent.
+#[cfg(test)]
+pub(crate) async fn fake_server(content_type: &'static str, body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+	use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let url = format!("http://{}", listener.local_addr().unwrap());
+	let handle = tokio::spawn(async move {
+		let (mut socket, _) = listener.accept().await.unwrap();
+		let mut received = Vec::new();
+		let mut buf = [0u8; 4096];
+		loop {
+			let n = socket.read(&mut buf).await.unwrap();
+			received.extend_from_slice(&buf[..n]);
+			let text = String::from_utf8_lossy(&received);
+			if let Some((head, rest)) = text.split_once("\r\n\r\n") {
+				let content_length = head
+					.lines()
+					.find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+					.unwrap_or(0);
+				if rest.len() >= content_length {
+					break;
+				}
+			}
+		}
+		let reply = format!(
+			"HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+			body.len()
+		);
+		socket.write_all(reply.as_bytes()).await.unwrap();
+		String::from_utf8(received).unwrap()
+	});
+	(url, handle)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Client, config::AppConfig};
+
+	#[tokio::test]
+	async fn mock_scripts_and_captures() {
+		let mock = MockBackend::new().fail(Error::Overloaded).reply("4").tool_call("search", serde_json::json!({"q": "rust"}));
+		let requests = mock.requests();
+		let client = Client::with_backend(AppConfig::default(), mock).retry(crate::RetryPolicy {
+			initial_backoff: std::time::Duration::ZERO,
+			..
+		});
+
+		assert_eq!(client.ask("2+2?").await.unwrap().text, "4");
+		assert_eq!(requests.len(), 2);
+		let response = client.temperature(0.5).ask("search").await.unwrap();
+		assert_eq!(response.tool_calls[0].name, "search");
+		assert_eq!(requests.last().unwrap().temperature, Some(0.5));
+	}
+
+	#[tokio::test]
+	async fn fixture_records_then_replays() {
+		let path = temp_path("fixture.json");
+		let fixture = |inner: MockBackend, mode| Client::with_backend(AppConfig::default(), Fixture::new(Box::new(inner), path.clone(), mode).unwrap());
+
+		let recording = fixture(MockBackend::new().reply("first").reply("second"), FixtureMode::Auto);
+		assert_eq!(recording.ask("again?").await.unwrap().text, "first");
+		assert_eq!(recording.ask("again?").await.unwrap().text, "second");
+
+		// no replies scripted, so anything not replayed would fail
+		let replaying = fixture(MockBackend::new(), FixtureMode::Replay);
+		assert_eq!(replaying.ask("again?").await.unwrap().text, "first");
+		assert_eq!(replaying.ask("again?").await.unwrap().text, "second");
+		assert!(matches!(replaying.ask("something else").await, Err(Error::Config(_))));
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}